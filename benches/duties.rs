@@ -0,0 +1,57 @@
+//! Cost of generating the messages of a node's validators, reading the duties precomputed for the
+//! epoch, and of computing those duties. The node runs a fixed set of validators while the size
+//! of the network grows.
+
+use std::collections::BTreeSet;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use npg::{
+    slot_generator::{DutyMode, SlotGenerator, ValId},
+    MsgType,
+};
+use slot_clock::Slot;
+use strum::IntoEnumIterator;
+
+const SLOTS_PER_EPOCH: u64 = 32;
+/// Validators run by the node.
+const LOCAL_VALIDATORS: u64 = 1000;
+
+fn slot_generator(total_validators: u64) -> SlotGenerator {
+    SlotGenerator::new(SLOTS_PER_EPOCH, 64, 128, 4, 16, total_validators)
+        .with_duty_mode(DutyMode::Spec)
+}
+
+fn slot_messages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("slot_messages");
+    group.sample_size(10);
+    let slot = Slot::new(SLOTS_PER_EPOCH + 5);
+    let validators: BTreeSet<_> = (0..LOCAL_VALIDATORS).map(ValId).collect();
+    for total_validators in [10_000, 100_000, 750_000] {
+        let slot_generator = slot_generator(total_validators);
+
+        group.bench_with_input(
+            BenchmarkId::new("epoch_duties", total_validators),
+            &validators,
+            |b, validators| {
+                b.iter(|| slot_generator.epoch_duties(slot.epoch(SLOTS_PER_EPOCH), validators))
+            },
+        );
+
+        let duties = slot_generator.epoch_duties(slot.epoch(SLOTS_PER_EPOCH), &validators);
+        group.bench_with_input(
+            BenchmarkId::new("messages", total_validators),
+            &duties,
+            |b, duties| {
+                b.iter(|| {
+                    MsgType::iter()
+                        .map(|msg_type| duties.messages(msg_type, slot).count())
+                        .sum::<usize>()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, slot_messages);
+criterion_main!(benches);
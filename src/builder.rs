@@ -1,42 +1,262 @@
-use std::{collections::HashSet, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use slot_clock::{Slot, SlotClock, SystemTimeSlotClock};
+use strum::IntoEnumIterator;
 
-use crate::{slot_generator::SlotGenerator, ValId};
+use crate::{
+    fork::{Fork, ForkSchedule},
+    jitter::Jitter,
+    sizes::PayloadMode,
+    slot_generator::{
+        AggregatorSelection, BlobCount, DutyMode, ProposerSelection, SlotGenerator,
+        SyncCommitteeSelection,
+    },
+    spec, MsgType, SlotPhase, ValId,
+};
 
 use super::Generator;
 
 const DEFAULT_SLOT_DURATION_SECONDS: u64 = 12;
 const DEFAULT_ATTESTATION_SUBNETS: u64 = 64;
-const DEFAULT_TARGET_AGGREGATORS: u64 = 16;
+const DEFAULT_TARGET_ATTESTATION_AGGREGATORS: u64 = 16;
+const DEFAULT_TARGET_SYNC_AGGREGATORS: u64 = 16;
 const DEFAULT_SYNC_COMMITTEE_SIZE: u64 = 512;
 const DEFAULT_SYNC_COMMITTEE_SUBNETS: u64 = 4;
 const DEFAULT_SLOTS_PER_EPOCH: u64 = 32;
+const DEFAULT_CLOCK_POLL_INTERVAL_MILLIS: u64 = 10;
 
-#[derive(Default)]
-pub struct GeneratorBuilder {
-    slot_clock: Option<SystemTimeSlotClock>,
+pub struct GeneratorBuilder<C = SystemTimeSlotClock> {
+    slot_clock: Option<C>,
+    /// Whether the slot clock was given already built.
+    slot_clock_is_impl: bool,
+    time_scale: Option<u32>,
+    clock_poll_interval: Option<Duration>,
+    attestation_offset: Option<Duration>,
+    aggregate_offset: Option<Duration>,
+    jitter: HashMap<MsgType, Jitter>,
+    operation_rates: HashMap<MsgType, f64>,
+    fork_schedule: Option<ForkSchedule>,
+    serve_light_clients: bool,
+    payload_mode: Option<PayloadMode>,
+    seed: Option<u64>,
+    duty_mode: Option<DutyMode>,
+    aggregator_selection: Option<AggregatorSelection>,
+    proposer_selection: Option<ProposerSelection>,
+    sync_committee_selection: Option<SyncCommitteeSelection>,
+    blob_count: Option<BlobCount>,
+    node_id: Option<[u8; 32]>,
+    custody_group_count: Option<u64>,
+    effective_balances: Option<Vec<u64>>,
     attestation_subnets: Option<u64>,
-    target_aggregators: Option<u64>,
+    target_attestation_aggregators: Option<u64>,
+    target_sync_aggregators: Option<u64>,
     sync_subnet_size: Option<u64>,
     sync_committee_subnets: Option<u64>,
     slots_per_epoch: Option<u64>,
     total_validators: Option<u64>,
 }
 
-impl GeneratorBuilder {
-    /// Slot clock based on system time.
+impl<C> Default for GeneratorBuilder<C> {
+    fn default() -> Self {
+        Self {
+            slot_clock: None,
+            slot_clock_is_impl: false,
+            time_scale: None,
+            clock_poll_interval: None,
+            attestation_offset: None,
+            aggregate_offset: None,
+            jitter: HashMap::new(),
+            operation_rates: HashMap::new(),
+            fork_schedule: None,
+            serve_light_clients: false,
+            payload_mode: None,
+            seed: None,
+            duty_mode: None,
+            aggregator_selection: None,
+            proposer_selection: None,
+            sync_committee_selection: None,
+            blob_count: None,
+            node_id: None,
+            custody_group_count: None,
+            effective_balances: None,
+            attestation_subnets: None,
+            target_attestation_aggregators: None,
+            target_sync_aggregators: None,
+            sync_subnet_size: None,
+            sync_committee_subnets: None,
+            slots_per_epoch: None,
+            total_validators: None,
+        }
+    }
+}
+
+impl<C: SlotClock> GeneratorBuilder<C> {
+    /// Slot clock built from the genesis and slot duration.
     pub fn slot_clock(
         &mut self,
         genesis_slot: u64,
         genesis_duration: Duration,
         slot_duration: Duration,
     ) -> &mut Self {
-        self.slot_clock = Some(SystemTimeSlotClock::new(
+        self.slot_clock = Some(C::new(
             Slot::new(genesis_slot),
             genesis_duration,
             slot_duration,
         ));
+        self.slot_clock_is_impl = false;
+        self
+    }
+
+    /// Already built slot clock. Useful to drive the generator with a manually advanced clock.
+    pub fn slot_clock_impl(&mut self, slot_clock: C) -> &mut Self {
+        self.slot_clock = Some(slot_clock);
+        self.slot_clock_is_impl = true;
+        self
+    }
+
+    /// Factor by which time is compressed. Slots last `slot_duration / time_scale` of real time,
+    /// while timings reported by the generator remain in simulated time. Slot numbers are
+    /// counted from genesis in simulated time, so it is usually set to the start of the run.
+    pub fn time_scale(&mut self, time_scale: u32) -> &mut Self {
+        self.time_scale = Some(time_scale);
+        self
+    }
+
+    /// Longest time the stream waits before checking the slot clock again. Clocks not based on
+    /// system time, such as manually advanced ones, can move forward while the stream waits for
+    /// the next message. Defaults to 10ms for a [`Self::slot_clock_impl`], and to waiting until
+    /// the next message is due otherwise.
+    pub fn clock_poll_interval(&mut self, clock_poll_interval: Duration) -> &mut Self {
+        self.clock_poll_interval = Some(clock_poll_interval);
+        self
+    }
+
+    /// Time since the start of the slot at which attestations and sync committee messages are
+    /// sent. Defaults to 1/3 of the slot.
+    pub fn attestation_offset(&mut self, attestation_offset: Duration) -> &mut Self {
+        self.attestation_offset = Some(attestation_offset);
+        self
+    }
+
+    /// Time since the start of the slot at which aggregates and sync committee contributions are
+    /// sent. Defaults to 2/3 of the slot.
+    pub fn aggregate_offset(&mut self, aggregate_offset: Duration) -> &mut Self {
+        self.aggregate_offset = Some(aggregate_offset);
+        self
+    }
+
+    /// Delay applied to each message of the given type after the start of its phase, so that
+    /// messages are spread over a window instead of sent in a single burst.
+    pub fn jitter(&mut self, msg_type: MsgType, jitter: Jitter) -> &mut Self {
+        self.jitter.insert(msg_type, jitter);
+        self
+    }
+
+    /// Mean number of operations of the given type (voluntary exits, slashings and BLS to
+    /// execution changes) sent by the validators per epoch. Their number in each epoch follows a
+    /// Poisson distribution. Defaults to none.
+    pub fn operation_rate(&mut self, msg_type: MsgType, rate: f64) -> &mut Self {
+        self.operation_rates.insert(msg_type, rate);
+        self
+    }
+
+    /// Epochs at which forks activate. Each fork turns message types on and off from its first
+    /// epoch: sync committee messages are sent since Altair, blob sidecars from Deneb until Fulu
+    /// replaces them with data columns, and payloads are laid out as in the fork. Defaults to
+    /// every fork up to [`Fork::Deneb`] being active from genesis.
+    pub fn fork_schedule(&mut self, fork_schedule: ForkSchedule) -> &mut Self {
+        self.fork_schedule = Some(fork_schedule);
+        self
+    }
+
+    /// Fork the chain is in from genesis, along with every fork before it. Shorthand for a
+    /// [`ForkSchedule::from_genesis`] schedule.
+    pub fn fork(&mut self, fork: Fork) -> &mut Self {
+        self.fork_schedule(ForkSchedule::from_genesis(fork))
+    }
+
+    /// Whether the node serves light clients, sending a finality and an optimistic update every
+    /// slot after the sync committee aggregates. Updates are sent since Altair. Defaults to
+    /// false.
+    pub fn serve_light_clients(&mut self, serve_light_clients: bool) -> &mut Self {
+        self.serve_light_clients = serve_light_clients;
+        self
+    }
+
+    /// How message payloads are filled. Defaults to [`PayloadMode::Zeroed`].
+    pub fn payload_mode(&mut self, payload_mode: PayloadMode) -> &mut Self {
+        self.payload_mode = Some(payload_mode);
+        self
+    }
+
+    /// Seed for the generator's randomness, used to jitter messages, to build their payloads and
+    /// to derive RANDAO-like mixes and selection proofs.
+    /// Runs with the same seed and configuration produce the same messages, in the same order and
+    /// with the same payloads. Drawn from entropy if not set.
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// How validators are assigned to attestation committees. Defaults to [`DutyMode::Simple`].
+    pub fn duty_mode(&mut self, duty_mode: DutyMode) -> &mut Self {
+        self.duty_mode = Some(duty_mode);
+        self
+    }
+
+    /// How aggregators are chosen among the members of a committee. Defaults to
+    /// [`AggregatorSelection::Fixed`].
+    pub fn aggregator_selection(&mut self, aggregator_selection: AggregatorSelection) -> &mut Self {
+        self.aggregator_selection = Some(aggregator_selection);
+        self
+    }
+
+    /// How the proposer of each slot is chosen. Defaults to [`ProposerSelection::RoundRobin`].
+    pub fn proposer_selection(&mut self, proposer_selection: ProposerSelection) -> &mut Self {
+        self.proposer_selection = Some(proposer_selection);
+        self
+    }
+
+    /// How the sync committee of each period is chosen. Defaults to
+    /// [`SyncCommitteeSelection::Rotating`].
+    pub fn sync_committee_selection(
+        &mut self,
+        sync_committee_selection: SyncCommitteeSelection,
+    ) -> &mut Self {
+        self.sync_committee_selection = Some(sync_committee_selection);
+        self
+    }
+
+    /// Number of blobs carried by each block, capped at the limit of the fork of the block.
+    /// Defaults to no blobs.
+    pub fn blob_count(&mut self, blob_count: BlobCount) -> &mut Self {
+        self.blob_count = Some(blob_count);
+        self
+    }
+
+    /// Id of the node running the validators, which determines the data columns it custodies.
+    /// Drawn from the seed by default.
+    pub fn node_id(&mut self, node_id: [u8; 32]) -> &mut Self {
+        self.node_id = Some(node_id);
+        self
+    }
+
+    /// Number of custody groups of the node. Defaults to the custody required by the effective
+    /// balance of its validators.
+    pub fn custody_group_count(&mut self, custody_group_count: u64) -> &mut Self {
+        self.custody_group_count = Some(custody_group_count);
+        self
+    }
+
+    /// Effective balance of each validator in the network, in Gwei, indexed by validator id.
+    /// Defaults to 32 ETH for every validator.
+    pub fn effective_balances(&mut self, effective_balances: Vec<u64>) -> &mut Self {
+        self.effective_balances = Some(effective_balances);
         self
     }
 
@@ -46,10 +266,22 @@ impl GeneratorBuilder {
         self
     }
 
-    /// Number of validators to designate as aggregators in the sync committee and attestation
-    /// subnets.
+    /// Number of validators to designate as aggregators in each attestation committee and each
+    /// sync subnet.
     pub fn target_aggregators(&mut self, aggregators: u64) -> &mut Self {
-        self.target_aggregators = Some(aggregators);
+        self.target_attestation_aggregators(aggregators)
+            .target_sync_aggregators(aggregators)
+    }
+
+    /// Number of validators to designate as aggregators in each attestation committee.
+    pub fn target_attestation_aggregators(&mut self, aggregators: u64) -> &mut Self {
+        self.target_attestation_aggregators = Some(aggregators);
+        self
+    }
+
+    /// Number of validators to designate as aggregators in each sync subnet.
+    pub fn target_sync_aggregators(&mut self, aggregators: u64) -> &mut Self {
+        self.target_sync_aggregators = Some(aggregators);
         self
     }
 
@@ -77,28 +309,96 @@ impl GeneratorBuilder {
         self
     }
 
-    pub fn build(&self, validators: HashSet<ValId>) -> Result<Generator, &'static str> {
+    pub fn build(&self, validators: HashSet<ValId>) -> Result<Generator<C>, &'static str> {
         let Self {
             slot_clock,
+            slot_clock_is_impl,
+            time_scale,
+            clock_poll_interval,
+            attestation_offset,
+            aggregate_offset,
+            jitter,
+            operation_rates,
+            fork_schedule,
+            serve_light_clients,
+            payload_mode,
+            seed,
+            duty_mode,
+            aggregator_selection,
+            proposer_selection,
+            sync_committee_selection,
+            blob_count,
+            node_id,
+            custody_group_count,
+            effective_balances,
             slots_per_epoch,
             attestation_subnets,
             sync_subnet_size,
             sync_committee_subnets,
-            target_aggregators,
+            target_attestation_aggregators,
+            target_sync_aggregators,
             total_validators,
         } = self;
 
         let slot_clock = slot_clock.clone().unwrap_or_else(|| {
-            SystemTimeSlotClock::new(
+            C::new(
                 Slot::new(0),
                 Duration::ZERO,
                 Duration::from_secs(DEFAULT_SLOT_DURATION_SECONDS),
             )
         });
 
+        let slot_duration = slot_clock.slot_duration();
+        let attestation_offset = attestation_offset
+            .unwrap_or_else(|| SlotPhase::Attestation.default_offset(slot_duration));
+        let aggregate_offset =
+            aggregate_offset.unwrap_or_else(|| SlotPhase::Aggregate.default_offset(slot_duration));
+        if attestation_offset > aggregate_offset {
+            return Err("attestation_offset must not be after aggregate_offset");
+        }
+        if aggregate_offset >= slot_duration {
+            return Err("aggregate_offset must be within the slot");
+        }
+
+        let time_scale = time_scale.unwrap_or(1);
+        if time_scale == 0 {
+            return Err("time_scale must be positive");
+        }
+        let slot_clock = if time_scale == 1 {
+            slot_clock
+        } else if *slot_clock_is_impl {
+            return Err("time_scale can't be applied to a slot_clock_impl");
+        } else {
+            let scaled_slot_duration = slot_duration / time_scale;
+            if scaled_slot_duration.is_zero() {
+                return Err("time_scale is too large for the slot duration");
+            }
+            C::new(
+                slot_clock.genesis_slot(),
+                slot_clock.genesis_duration(),
+                scaled_slot_duration,
+            )
+        };
+        if let Some(now) = slot_clock.now_duration() {
+            if crate::simulated_time(now, slot_clock.genesis_duration(), time_scale).is_none() {
+                // Timings are reported in simulated time.
+                return Err("time_scale is too large for the time since genesis");
+            }
+        }
+
+        let clock_poll_interval = clock_poll_interval.or_else(|| {
+            slot_clock_is_impl.then(|| Duration::from_millis(DEFAULT_CLOCK_POLL_INTERVAL_MILLIS))
+        });
+        if clock_poll_interval.is_some_and(|interval| interval.is_zero()) {
+            return Err("clock_poll_interval must be positive");
+        }
+
         let total_validators = total_validators.ok_or("total_validators not set")?;
         let attestation_subnets = attestation_subnets.unwrap_or(DEFAULT_ATTESTATION_SUBNETS);
-        let target_aggregators = target_aggregators.unwrap_or(DEFAULT_TARGET_AGGREGATORS);
+        let target_attestation_aggregators =
+            target_attestation_aggregators.unwrap_or(DEFAULT_TARGET_ATTESTATION_AGGREGATORS);
+        let target_sync_aggregators =
+            target_sync_aggregators.unwrap_or(DEFAULT_TARGET_SYNC_AGGREGATORS);
         let sync_subnet_size = sync_subnet_size.unwrap_or(DEFAULT_SYNC_COMMITTEE_SIZE);
         let sync_committee_subnets =
             sync_committee_subnets.unwrap_or(DEFAULT_SYNC_COMMITTEE_SUBNETS);
@@ -124,62 +424,146 @@ impl GeneratorBuilder {
             // Sync subnets make sense
             return Err("sync_committee_subnets must be positive");
         }
-        if sync_subnet_size
+        let sync_committee_size = sync_subnet_size
             .checked_mul(sync_committee_subnets)
-            .ok_or("sync committee size is too large")?
-            > total_validators
+            .ok_or("sync committee size is too large")?;
+        if sync_committee_selection.unwrap_or_default() == SyncCommitteeSelection::Rotating
+            && sync_committee_size > total_validators
         {
-            // There must be enough validators to cover the sync committee.
+            // There must be enough validators to cover the sync committee, unless they can hold
+            // several positions in it.
             return Err("not enough validators to reach the sync committees size");
         }
-        /*
-        if target_aggregators
-            .checked_mul(attestation_subnets)
-            .ok_or("total attestation aggregators across the network is too large")?
-            > total_validators
-        {
-            // There must be enough validators to cover the aggregators requirements.
-            return Err(
-                "not enough validators to reach the target aggregators in the attestation subnets",
-            );
+        let fork_schedule = fork_schedule.clone().unwrap_or_default();
+        fork_schedule.validate()?;
+        let blob_count = blob_count.unwrap_or_default();
+        let max_blobs_per_block = Fork::iter()
+            .filter(|fork| fork_schedule.fork_epoch(*fork).is_some())
+            .map(|fork| fork.max_blobs_per_block())
+            .max()
+            .unwrap_or_default();
+        if blob_count.max() > max_blobs_per_block {
+            // Blocks of each fork are further capped at its own limit.
+            return Err("blocks can't carry more blobs than the scheduled forks allow");
         }
-        if target_aggregators
-            .checked_mul(sync_committee_subnets)
-            .ok_or("target sync aggregators across the network is too large")?
-            > total_validators
+        if let Some(effective_balances) = effective_balances {
+            if effective_balances.len() as u64 != total_validators {
+                return Err("effective_balances must have one entry per validator");
+            }
+            if effective_balances
+                .iter()
+                .any(|balance| *balance > spec::MAX_EFFECTIVE_BALANCE_ELECTRA)
+            {
+                return Err("effective balances can't be over 2048 ETH");
+            }
+            if effective_balances.iter().all(|balance| *balance == 0) {
+                // Someone must be able to propose.
+                return Err("some validator must have a positive effective balance");
+            }
+        }
+        let custody_group_count = custody_group_count.unwrap_or_else(|| {
+            let validators_balance = validators
+                .iter()
+                .map(|val_id| {
+                    effective_balances
+                        .as_ref()
+                        .map_or(spec::MAX_EFFECTIVE_BALANCE, |balances| {
+                            balances[**val_id as usize]
+                        })
+                })
+                .sum();
+            spec::custody_group_count((!validators.is_empty()).then_some(validators_balance))
+        });
+        if !(spec::CUSTODY_REQUIREMENT..=spec::NUMBER_OF_CUSTODY_GROUPS)
+            .contains(&custody_group_count)
         {
-            // There must be enough validators to cover the aggregators requirements.
             return Err(
-                "not enough validators to reach the target aggregators in the sync committees",
+                "custody_group_count must be between CUSTODY_REQUIREMENT and the number of groups",
             );
         }
-        */
+        for (msg_type, rate) in operation_rates {
+            if !msg_type.is_operation() {
+                return Err("operation rates can only be set for operation message types");
+            }
+            if !rate.is_finite() || *rate < 0.0 {
+                return Err("operation rates must be finite and not negative");
+            }
+        }
+        if target_attestation_aggregators == 0 {
+            // Attestations get aggregated
+            return Err("target_attestation_aggregators must be positive");
+        }
+        if target_sync_aggregators == 0 {
+            // Sync committee messages get aggregated
+            return Err("target_sync_aggregators must be positive");
+        }
+        let committees_per_epoch = slots_per_epoch.saturating_mul(spec::committee_count_per_slot(
+            total_validators,
+            slots_per_epoch,
+            attestation_subnets,
+        ));
+        if target_attestation_aggregators > total_validators.div_ceil(committees_per_epoch) {
+            // Aggregators are members of the committee, so the largest committees of the epoch
+            // must be able to hold them.
+            return Err("target_attestation_aggregators can't be larger than the committees");
+        }
+        if target_sync_aggregators > sync_subnet_size {
+            // Aggregators are members of the sync subnet.
+            return Err("target_sync_aggregators can't be larger than sync_subnet_size");
+        }
 
+        // Start on the next slot, or at genesis if it has not happened yet.
         let next_slot = slot_clock
-            .duration_to_next_slot()
-            .expect("system clock is unlikely to fail");
+            .now()
+            .map_or_else(|| slot_clock.genesis_slot(), |slot| slot + 1);
+        let mut rng = seed.map_or_else(ChaCha8Rng::from_entropy, ChaCha8Rng::seed_from_u64);
         let slot_generator = SlotGenerator::new(
             slots_per_epoch,
             attestation_subnets,
             sync_subnet_size,
             sync_committee_subnets,
-            target_aggregators,
+            target_attestation_aggregators,
             total_validators,
-        );
+        )
+        .with_duty_mode(duty_mode.unwrap_or_default())
+        .with_aggregator_selection(aggregator_selection.unwrap_or_default())
+        .with_proposer_selection(proposer_selection.unwrap_or_default())
+        .with_target_sync_aggregators(target_sync_aggregators)
+        .with_sync_committee_selection(sync_committee_selection.unwrap_or_default())
+        .with_blob_count(blob_count)
+        .with_operation_rates(operation_rates.clone())
+        .with_fork_schedule(fork_schedule)
+        .with_light_client_server(*serve_light_clients)
+        .with_effective_balances(effective_balances.clone().unwrap_or_default())
+        .with_seed(rng.gen());
 
-        // Slot interval
-        let interval = tokio::time::interval_at(
-            tokio::time::Instant::now() + next_slot,
-            slot_clock.slot_duration() / 3,
-        );
+        let payload_seed = rng.gen();
+        let node_id = node_id.unwrap_or_else(|| rng.gen());
+        let custody_columns = spec::get_custody_groups(node_id, custody_group_count)
+            .into_iter()
+            .flat_map(spec::compute_columns_for_custody_group)
+            .collect();
 
         Ok(Generator {
             slot_clock,
             slot_generator,
-            validators,
+            duties: None,
+            validators: validators.into_iter().collect(),
             queued_messages: Default::default(),
-            interval,
-            interval_count: 0,
+            queued_count: 0,
+            jitter: jitter.clone(),
+            payload_seed,
+            payload_mode: payload_mode.unwrap_or_default(),
+            rng,
+            node_id,
+            custody_columns,
+            time_scale,
+            clock_poll_interval,
+            attestation_offset,
+            aggregate_offset,
+            next_slot,
+            next_phase: SlotPhase::Block,
+            sleep: None,
         })
     }
 }
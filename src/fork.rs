@@ -0,0 +1,227 @@
+//! Consensus forks, which determine the messages validators send and their sizes.
+
+use std::collections::BTreeMap;
+
+use slot_clock::Slot;
+use strum::{EnumIter, IntoEnumIterator};
+use types::Epoch;
+
+use crate::{slot_generator::BlobDistribution, spec, MsgType};
+
+/// Forks of the beacon chain, in activation order.
+#[derive(
+    EnumIter, Debug, strum::Display, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+#[strum(serialize_all = "lowercase")]
+pub enum Fork {
+    Phase0,
+    Altair,
+    Bellatrix,
+    Capella,
+    #[default]
+    Deneb,
+    Electra,
+    Fulu,
+}
+
+impl Fork {
+    /// Version of the fork on mainnet.
+    pub fn mainnet_version(&self) -> [u8; 4] {
+        match self {
+            Fork::Phase0 => [0, 0, 0, 0],
+            Fork::Altair => [1, 0, 0, 0],
+            Fork::Bellatrix => [2, 0, 0, 0],
+            Fork::Capella => [3, 0, 0, 0],
+            Fork::Deneb => [4, 0, 0, 0],
+            Fork::Electra => [5, 0, 0, 0],
+            Fork::Fulu => [6, 0, 0, 0],
+        }
+    }
+
+    /// How blobs are distributed over gossip in the fork, if blocks carry any. Data columns
+    /// replace blob sidecars since Fulu.
+    pub fn blob_distribution(&self) -> Option<BlobDistribution> {
+        match self {
+            Fork::Phase0 | Fork::Altair | Fork::Bellatrix | Fork::Capella => None,
+            Fork::Deneb | Fork::Electra => Some(BlobDistribution::BlobSidecars),
+            Fork::Fulu => Some(BlobDistribution::DataColumns),
+        }
+    }
+
+    /// Largest number of blobs a block of the fork can carry. Blocks carry no blobs before
+    /// Deneb.
+    pub fn max_blobs_per_block(&self) -> u64 {
+        match self {
+            Fork::Phase0 | Fork::Altair | Fork::Bellatrix | Fork::Capella => 0,
+            Fork::Deneb => spec::MAX_BLOBS_PER_BLOCK,
+            Fork::Electra | Fork::Fulu => spec::MAX_BLOBS_PER_BLOCK_ELECTRA,
+        }
+    }
+
+    /// Number of subnets blob sidecars are published on. Blob sidecars are only sent in Deneb
+    /// and Electra.
+    pub fn blob_sidecar_subnet_count(&self) -> u64 {
+        if *self >= Fork::Electra {
+            spec::BLOB_SIDECAR_SUBNET_COUNT_ELECTRA
+        } else {
+            spec::BLOB_SIDECAR_SUBNET_COUNT
+        }
+    }
+}
+
+/// Epochs at which forks activate. Phase0 is active from genesis, and forks without an epoch are
+/// never reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForkSchedule {
+    activation_epochs: BTreeMap<Fork, Epoch>,
+}
+
+impl Default for ForkSchedule {
+    fn default() -> Self {
+        Self::from_genesis(Fork::default())
+    }
+}
+
+impl ForkSchedule {
+    /// Schedule in which the fork and every fork before it are active from genesis.
+    pub fn from_genesis(fork: Fork) -> Self {
+        Self {
+            activation_epochs: Fork::iter()
+                .take_while(|scheduled| *scheduled <= fork)
+                .map(|scheduled| (scheduled, Epoch::new(0)))
+                .collect(),
+        }
+    }
+
+    /// Sets the epoch at which the fork activates.
+    pub fn with_fork_epoch(mut self, fork: Fork, epoch: Epoch) -> Self {
+        self.activation_epochs.insert(fork, epoch);
+        self
+    }
+
+    /// Epoch at which the fork activates, if scheduled.
+    pub fn fork_epoch(&self, fork: Fork) -> Option<Epoch> {
+        self.activation_epochs.get(&fork).copied()
+    }
+
+    /// Fork active in the epoch.
+    pub fn fork_at_epoch(&self, epoch: Epoch) -> Fork {
+        self.activation_epochs
+            .iter()
+            .rev()
+            .find(|(_, activation_epoch)| **activation_epoch <= epoch)
+            .map_or(Fork::Phase0, |(fork, _)| *fork)
+    }
+
+    /// Checks that Phase0 starts at genesis and that forks activate in order, each no earlier
+    /// than the one before it.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.fork_epoch(Fork::Phase0) != Some(Epoch::new(0)) {
+            return Err("Phase0 must be active from genesis");
+        }
+        for (fork, next) in Fork::iter().zip(Fork::iter().skip(1)) {
+            match (self.fork_epoch(fork), self.fork_epoch(next)) {
+                (None, Some(_)) => return Err("forks can't be scheduled before the previous one"),
+                (Some(epoch), Some(next_epoch)) if next_epoch < epoch => {
+                    return Err("forks can't activate before the previous one")
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What identifies the forks of a chain on the network: their schedule, versions and the genesis
+/// validators root, from which the fork digest of each gossip topic is derived, along with the
+/// subnets its topics are spread over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForkContext {
+    fork_schedule: ForkSchedule,
+    slots_per_epoch: u64,
+    genesis_validators_root: [u8; 32],
+    /// Version of each fork. Forks without one use their mainnet version.
+    fork_versions: BTreeMap<Fork, [u8; 4]>,
+    attestation_subnets: u64,
+    sync_committee_subnets: u64,
+}
+
+impl ForkContext {
+    pub fn new(
+        fork_schedule: ForkSchedule,
+        slots_per_epoch: u64,
+        genesis_validators_root: [u8; 32],
+    ) -> Self {
+        Self {
+            fork_schedule,
+            slots_per_epoch,
+            genesis_validators_root,
+            fork_versions: BTreeMap::new(),
+            attestation_subnets: spec::ATTESTATION_SUBNET_COUNT,
+            sync_committee_subnets: spec::SYNC_COMMITTEE_SUBNET_COUNT,
+        }
+    }
+
+    /// Sets the number of attestation and sync committee subnets, for chains other than mainnet.
+    pub fn with_subnet_counts(
+        mut self,
+        attestation_subnets: u64,
+        sync_committee_subnets: u64,
+    ) -> Self {
+        self.attestation_subnets = attestation_subnets;
+        self.sync_committee_subnets = sync_committee_subnets;
+        self
+    }
+
+    /// Number of subnets messages of the given type are published over in the fork.
+    pub fn subnet_count(&self, msg_type: MsgType, fork: Fork) -> Option<u64> {
+        match msg_type {
+            MsgType::BlobSidecar => Some(fork.blob_sidecar_subnet_count()),
+            MsgType::Attestation => Some(self.attestation_subnets),
+            MsgType::SyncCommitteeMessage => Some(self.sync_committee_subnets),
+            _ => msg_type.subnet_count(),
+        }
+    }
+
+    /// Sets the version of a fork, for chains other than mainnet.
+    pub fn with_fork_version(mut self, fork: Fork, version: [u8; 4]) -> Self {
+        self.fork_versions.insert(fork, version);
+        self
+    }
+
+    pub fn fork_version(&self, fork: Fork) -> [u8; 4] {
+        self.fork_versions
+            .get(&fork)
+            .copied()
+            .unwrap_or_else(|| fork.mainnet_version())
+    }
+
+    /// Fork active in the slot.
+    pub fn fork_at(&self, slot: Slot) -> Fork {
+        self.fork_schedule
+            .fork_at_epoch(slot.epoch(self.slots_per_epoch))
+    }
+
+    /// Digest of the fork, as in `compute_fork_digest`. Since Fulu the blob parameters are mixed
+    /// in. The blob schedule is taken to be empty, so those are Electra's.
+    pub fn fork_digest(&self, fork: Fork) -> [u8; 4] {
+        let digest =
+            spec::compute_fork_digest(self.fork_version(fork), self.genesis_validators_root);
+        if fork >= Fork::Fulu {
+            let electra_epoch = self
+                .fork_schedule
+                .fork_epoch(Fork::Electra)
+                .map_or(0, |epoch| epoch.as_u64());
+            spec::mix_blob_parameters(digest, electra_epoch, spec::MAX_BLOBS_PER_BLOCK_ELECTRA)
+        } else {
+            digest
+        }
+    }
+
+    /// Scheduled fork with the given digest.
+    pub fn fork_from_digest(&self, fork_digest: [u8; 4]) -> Option<Fork> {
+        Fork::iter()
+            .filter(|fork| self.fork_schedule.fork_epoch(*fork).is_some())
+            .find(|fork| self.fork_digest(*fork) == fork_digest)
+    }
+}
@@ -0,0 +1,47 @@
+//! Random delays between the start of the slot phase in which a message is due and the moment a
+//! validator sends it.
+
+use std::time::Duration;
+
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, Exp, Normal};
+
+/// Model of the delay between the start of a message's phase and the moment a validator sends it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jitter {
+    /// Delay drawn uniformly from `[0, max)`.
+    Uniform { max: Duration },
+    /// Delay drawn from a normal distribution. Negative samples are sent right away.
+    Normal { mean: Duration, std_dev: Duration },
+    /// Delay drawn from an exponential distribution with the given mean.
+    Exponential { mean: Duration },
+}
+
+impl Jitter {
+    pub fn sample(&self, rng: &mut ChaCha8Rng) -> Duration {
+        match *self {
+            Jitter::Uniform { max } => {
+                if max.is_zero() {
+                    Duration::ZERO
+                } else {
+                    rng.gen_range(Duration::ZERO..max)
+                }
+            }
+            Jitter::Normal { mean, std_dev } => {
+                let normal = Normal::new(mean.as_secs_f64(), std_dev.as_secs_f64())
+                    .expect("durations are finite and not negative");
+                Duration::from_secs_f64(normal.sample(rng).max(0.0))
+            }
+            Jitter::Exponential { mean } => {
+                if mean.is_zero() {
+                    Duration::ZERO
+                } else {
+                    let exp = Exp::new(1.0 / mean.as_secs_f64())
+                        .expect("rate of a positive duration is positive");
+                    Duration::from_secs_f64(exp.sample(rng))
+                }
+            }
+        }
+    }
+}
@@ -1,45 +1,174 @@
 use std::{
-    collections::{HashSet, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    future::Future,
+    ops::Range,
     pin::Pin,
     task::Poll,
+    time::{Duration, Instant},
 };
 
-//use futures::{stream::Stream, Future};
+use fork::{Fork, ForkContext, ForkSchedule};
 use futures::stream::Stream;
+use jitter::Jitter;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use sizes::PayloadMode;
 use slot_clock::{Slot, SlotClock, SystemTimeSlotClock};
-use slot_generator::{SlotGenerator, Subnet, ValId};
+use slot_generator::{BlobDistribution, EpochDuties, SlotGenerator, Subnet, ValId};
 use strum::{EnumIter, IntoEnumIterator};
-// use tokio::time::{sleep, Sleep};
+use tokio::time::Sleep;
+use types::Epoch;
 
 pub mod builder;
+pub mod fork;
+pub mod jitter;
 pub mod sizes;
 pub mod slot_generator;
+pub mod spec;
 #[cfg(test)]
 mod tests;
+pub mod topic;
 
-#[derive(EnumIter, Debug, strum::Display, Clone, Copy)]
+#[derive(EnumIter, Debug, strum::Display, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[strum(serialize_all = "kebab_case")]
 pub enum MsgType {
     BeaconBlock,
+    BlobSidecar,
+    DataColumnSidecar,
     AggregateAndProofAttestation,
     Attestation,
     SignedContributionAndProof,
     SyncCommitteeMessage,
+    LightClientFinalityUpdate,
+    LightClientOptimisticUpdate,
+    VoluntaryExit,
+    ProposerSlashing,
+    AttesterSlashing,
+    BlsToExecutionChange,
 }
 
-pub struct Generator {
-    /// Slot clock based on system time.
-    slot_clock: SystemTimeSlotClock,
+impl MsgType {
+    /// Phase of the slot in which messages of this type are sent.
+    pub fn phase(&self) -> SlotPhase {
+        match self {
+            MsgType::BeaconBlock | MsgType::BlobSidecar | MsgType::DataColumnSidecar => {
+                SlotPhase::Block
+            }
+            MsgType::Attestation | MsgType::SyncCommitteeMessage => SlotPhase::Attestation,
+            // Light client updates follow the sync committee aggregates.
+            MsgType::AggregateAndProofAttestation
+            | MsgType::SignedContributionAndProof
+            | MsgType::LightClientFinalityUpdate
+            | MsgType::LightClientOptimisticUpdate => SlotPhase::Aggregate,
+            // Operations are not tied to a point of the slot. Jitter spreads them over it.
+            MsgType::VoluntaryExit
+            | MsgType::ProposerSlashing
+            | MsgType::AttesterSlashing
+            | MsgType::BlsToExecutionChange => SlotPhase::Block,
+        }
+    }
+
+    /// Whether messages of this type are operations gossiped to the operation pool, sent at a
+    /// random rate instead of as per-slot duties.
+    pub fn is_operation(&self) -> bool {
+        matches!(
+            self,
+            MsgType::VoluntaryExit
+                | MsgType::ProposerSlashing
+                | MsgType::AttesterSlashing
+                | MsgType::BlsToExecutionChange
+        )
+    }
+
+    /// Whether messages of this type are sent in the fork.
+    pub fn is_sent_in(&self, fork: Fork) -> bool {
+        match self {
+            MsgType::SyncCommitteeMessage
+            | MsgType::SignedContributionAndProof
+            | MsgType::LightClientFinalityUpdate
+            | MsgType::LightClientOptimisticUpdate => fork >= Fork::Altair,
+            MsgType::BlobSidecar => {
+                fork.blob_distribution() == Some(BlobDistribution::BlobSidecars)
+            }
+            MsgType::DataColumnSidecar => {
+                fork.blob_distribution() == Some(BlobDistribution::DataColumns)
+            }
+            MsgType::BlsToExecutionChange => fork >= Fork::Capella,
+            MsgType::BeaconBlock
+            | MsgType::AggregateAndProofAttestation
+            | MsgType::Attestation
+            | MsgType::VoluntaryExit
+            | MsgType::ProposerSlashing
+            | MsgType::AttesterSlashing => true,
+        }
+    }
+}
+
+/// Points of the slot at which validators send their messages.
+#[derive(EnumIter, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SlotPhase {
+    /// Start of the slot.
+    Block,
+    /// 1/3 of the slot.
+    Attestation,
+    /// 2/3 of the slot.
+    Aggregate,
+}
+
+impl SlotPhase {
+    /// Default time since the start of the slot at which the phase begins.
+    pub fn default_offset(&self, slot_duration: Duration) -> Duration {
+        match self {
+            SlotPhase::Block => Duration::ZERO,
+            SlotPhase::Attestation => slot_duration / 3,
+            SlotPhase::Aggregate => slot_duration * 2 / 3,
+        }
+    }
+}
+
+pub struct Generator<C = SystemTimeSlotClock> {
+    /// Slot clock driving the generator.
+    slot_clock: C,
+    /// Factor by which the slot clock runs faster than simulated time.
+    time_scale: u32,
+    /// Longest real time the stream waits before checking the slot clock again, for clocks that
+    /// can move forward while it waits.
+    clock_poll_interval: Option<Duration>,
     /// Slot messages generator.
     slot_generator: SlotGenerator,
-    /// Validator managed by this node.
-    validators: HashSet<ValId>,
-    /// Messages pending to be returned.
-    queued_messages: VecDeque<Message>,
-    /// Slot interval.
-    interval: tokio::time::Interval,
-    /// Slot interval count. The interval occurs every 1/3 of a slot. So we keep track where we are
-    interval_count: u8,
+    /// Duties of the validators in the epoch of `next_slot`.
+    duties: Option<EpochDuties>,
+    /// Validator managed by this node. Kept sorted so that messages are generated in the same
+    /// order on every run.
+    validators: BTreeSet<ValId>,
+    /// Messages pending to be returned, by the simulated time at which they are due and the order
+    /// in which they were queued.
+    queued_messages: BTreeMap<(Duration, u64), TimedMessage>,
+    /// Number of messages queued so far.
+    queued_count: u64,
+    /// Delay applied to messages of each type after the start of their phase.
+    jitter: HashMap<MsgType, Jitter>,
+    /// Random number generator used to jitter messages.
+    rng: ChaCha8Rng,
+    /// Seed from which message payloads are derived.
+    payload_seed: u64,
+    /// How message payloads are filled.
+    payload_mode: PayloadMode,
+    /// Id of the node running the validators.
+    node_id: [u8; 32],
+    /// Data columns custodied by the node, in ascending order.
+    custody_columns: Vec<u64>,
+    /// Time since the start of the slot at which attestations are sent, in simulated time.
+    attestation_offset: Duration,
+    /// Time since the start of the slot at which aggregates are sent, in simulated time.
+    aggregate_offset: Duration,
+    /// Next slot with phases pending to be processed.
+    next_slot: Slot,
+    /// Next phase of `next_slot` to be processed.
+    next_phase: SlotPhase,
+    /// Timer to wake up the stream when the next phase is due. Created on first poll so that
+    /// building a generator does not require a tokio runtime.
+    sleep: Option<Pin<Box<Sleep>>>,
 }
 
 #[derive(Clone, Hash, PartialEq, Eq)]
@@ -48,6 +177,22 @@ pub enum Message {
         proposer: ValId,
         slot: Slot,
     },
+    BlobSidecar {
+        proposer: ValId,
+        /// Index of the blob within the block.
+        index: u64,
+        subnet: Subnet,
+        slot: Slot,
+    },
+    DataColumnSidecar {
+        proposer: ValId,
+        /// Index of the column.
+        index: u64,
+        subnet: Subnet,
+        /// Number of blobs of the block, each contributing a cell to the column.
+        blob_count: u64,
+        slot: Slot,
+    },
     AggregateAndProofAttestation {
         aggregator: ValId,
         subnet: Subnet,
@@ -68,140 +213,444 @@ pub enum Message {
         subnet: Subnet,
         slot: Slot,
     },
+    LightClientFinalityUpdate {
+        slot: Slot,
+    },
+    LightClientOptimisticUpdate {
+        slot: Slot,
+    },
+    VoluntaryExit {
+        validator: ValId,
+        slot: Slot,
+    },
+    ProposerSlashing {
+        /// Slashed validator.
+        validator: ValId,
+        slot: Slot,
+    },
+    AttesterSlashing {
+        /// Slashed validator.
+        validator: ValId,
+        slot: Slot,
+    },
+    BlsToExecutionChange {
+        validator: ValId,
+        slot: Slot,
+    },
+}
+
+impl Message {
+    pub fn slot(&self) -> Slot {
+        match self {
+            Message::BeaconBlock { slot, .. }
+            | Message::BlobSidecar { slot, .. }
+            | Message::DataColumnSidecar { slot, .. }
+            | Message::AggregateAndProofAttestation { slot, .. }
+            | Message::Attestation { slot, .. }
+            | Message::SignedContributionAndProof { slot, .. }
+            | Message::SyncCommitteeMessage { slot, .. }
+            | Message::LightClientFinalityUpdate { slot, .. }
+            | Message::LightClientOptimisticUpdate { slot, .. }
+            | Message::VoluntaryExit { slot, .. }
+            | Message::ProposerSlashing { slot, .. }
+            | Message::AttesterSlashing { slot, .. }
+            | Message::BlsToExecutionChange { slot, .. } => *slot,
+        }
+    }
+
+    pub fn msg_type(&self) -> MsgType {
+        match self {
+            Message::BeaconBlock { .. } => MsgType::BeaconBlock,
+            Message::BlobSidecar { .. } => MsgType::BlobSidecar,
+            Message::DataColumnSidecar { .. } => MsgType::DataColumnSidecar,
+            Message::AggregateAndProofAttestation { .. } => MsgType::AggregateAndProofAttestation,
+            Message::Attestation { .. } => MsgType::Attestation,
+            Message::SignedContributionAndProof { .. } => MsgType::SignedContributionAndProof,
+            Message::SyncCommitteeMessage { .. } => MsgType::SyncCommitteeMessage,
+            Message::LightClientFinalityUpdate { .. } => MsgType::LightClientFinalityUpdate,
+            Message::LightClientOptimisticUpdate { .. } => MsgType::LightClientOptimisticUpdate,
+            Message::VoluntaryExit { .. } => MsgType::VoluntaryExit,
+            Message::ProposerSlashing { .. } => MsgType::ProposerSlashing,
+            Message::AttesterSlashing { .. } => MsgType::AttesterSlashing,
+            Message::BlsToExecutionChange { .. } => MsgType::BlsToExecutionChange,
+        }
+    }
+
+    /// Stable encoding of the message from which its payload is derived: the length-prefixed
+    /// name of its type followed by its fields as little-endian integers.
+    fn seed_bytes(&self) -> Vec<u8> {
+        let fields = match self {
+            Message::BeaconBlock { proposer, slot } => vec![**proposer, slot.as_u64()],
+            Message::BlobSidecar {
+                proposer,
+                index,
+                subnet,
+                slot,
+            } => vec![**proposer, *index, **subnet, slot.as_u64()],
+            Message::DataColumnSidecar {
+                proposer,
+                index,
+                subnet,
+                blob_count,
+                slot,
+            } => vec![**proposer, *index, **subnet, *blob_count, slot.as_u64()],
+            Message::AggregateAndProofAttestation {
+                aggregator: validator,
+                subnet,
+                slot,
+            }
+            | Message::Attestation {
+                attester: validator,
+                subnet,
+                slot,
+            }
+            | Message::SignedContributionAndProof {
+                validator,
+                subnet,
+                slot,
+            }
+            | Message::SyncCommitteeMessage {
+                validator,
+                subnet,
+                slot,
+            } => vec![**validator, **subnet, slot.as_u64()],
+            Message::LightClientFinalityUpdate { slot }
+            | Message::LightClientOptimisticUpdate { slot } => vec![slot.as_u64()],
+            Message::VoluntaryExit { validator, slot }
+            | Message::ProposerSlashing { validator, slot }
+            | Message::AttesterSlashing { validator, slot }
+            | Message::BlsToExecutionChange { validator, slot } => {
+                vec![**validator, slot.as_u64()]
+            }
+        };
+        let name = self.msg_type().to_string();
+        let mut bytes = vec![name.len() as u8];
+        bytes.extend_from_slice(name.as_bytes());
+        for field in fields {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// A message along with the point of its slot at which it is sent.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TimedMessage {
+    pub message: Message,
+    pub phase: SlotPhase,
+    /// Time since the start of the slot at which the message is sent, in simulated time.
+    pub offset: Duration,
+}
+
+/// A message returned by the generator's stream, along with when it was due and when it came out.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EmittedMessage {
+    pub message: Message,
+    pub slot: Slot,
+    pub epoch: Epoch,
+    /// Fork active in the message's epoch.
+    pub fork: Fork,
+    pub phase: SlotPhase,
+    /// Time at which the message was due, as a duration since the slot clock's origin (the UNIX
+    /// epoch for system time clocks), in simulated time.
+    pub scheduled: Duration,
+    /// Instant at which the message was returned by the stream.
+    pub emitted: Instant,
+    /// Time, in simulated time, between the message being due and being returned.
+    pub delay: Duration,
 }
 
 const EPOCHS_PER_SYNC_COMMITTEE_PERIOD: u64 = 256;
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Random number generator seeded with the hash of `preimage`. Both the hash and the generator
+/// are portable, so seeded runs produce the same values on every platform and toolchain.
+pub(crate) fn seeded_rng(preimage: &[u8]) -> ChaCha8Rng {
+    let seed = eth2_hashing::hash(preimage)
+        .try_into()
+        .expect("hashes are 32 bytes long");
+    ChaCha8Rng::from_seed(seed)
+}
+
+/// Time of a slot clock compressed by the time scale, as a duration since the clock's origin.
+/// Time is compressed on both sides of genesis, so waits until genesis are scaled too. `None` if
+/// it does not fit in a [`Duration`].
+fn simulated_time(clock_time: Duration, genesis: Duration, time_scale: u32) -> Option<Duration> {
+    if clock_time >= genesis {
+        (clock_time - genesis)
+            .checked_mul(time_scale)
+            .and_then(|since_genesis| genesis.checked_add(since_genesis))
+    } else {
+        (genesis - clock_time)
+            .checked_mul(time_scale)
+            .map(|until_genesis| genesis.saturating_sub(until_genesis))
+    }
+}
 
 impl Generator {
+    /// Builder for a generator driven by the system time. Use
+    /// [`builder::GeneratorBuilder::slot_clock_impl`] to drive it with any other [`SlotClock`].
     pub fn builder() -> builder::GeneratorBuilder {
         builder::GeneratorBuilder::default()
     }
+}
 
-    /// Time since last slot
-    pub fn time_since_last_slot(&self) -> std::time::Duration {
+impl<C: SlotClock> Generator<C> {
+    /// Time since last slot, in simulated time.
+    pub fn time_since_last_slot(&self) -> Duration {
         self.slot_clock.slot_duration().saturating_sub(
             self.slot_clock
                 .duration_to_next_slot()
-                .unwrap_or(std::time::Duration::ZERO),
+                .unwrap_or(Duration::ZERO),
+        ) * self.time_scale
+    }
+
+    /// Duration of a slot, in simulated time.
+    pub fn slot_duration(&self) -> Duration {
+        self.slot_clock.slot_duration() * self.time_scale
+    }
+
+    /// Id of the node running the validators.
+    pub fn node_id(&self) -> [u8; 32] {
+        self.node_id
+    }
+
+    /// Data columns custodied by the node, in ascending order.
+    pub fn custody_columns(&self) -> &[u64] {
+        &self.custody_columns
+    }
+
+    /// Subnets of the data columns custodied by the node, which it subscribes to in order to
+    /// receive them.
+    pub fn data_column_subnets(&self) -> BTreeSet<Subnet> {
+        self.custody_columns
+            .iter()
+            .map(|column| Subnet(spec::compute_subnet_for_data_column_sidecar(*column)))
+            .collect()
+    }
+
+    /// Time since the start of the slot at which the phase begins, in simulated time.
+    pub fn phase_offset(&self, phase: SlotPhase) -> Duration {
+        match phase {
+            SlotPhase::Block => Duration::ZERO,
+            SlotPhase::Attestation => self.attestation_offset,
+            SlotPhase::Aggregate => self.aggregate_offset,
+        }
+    }
+
+    /// Epochs at which forks activate.
+    pub fn fork_schedule(&self) -> &ForkSchedule {
+        self.slot_generator.fork_schedule()
+    }
+
+    /// Context from which the gossip topics of the generator's messages are derived, for a chain
+    /// with the given genesis validators root, mainnet fork versions and the generator's subnets.
+    pub fn fork_context(&self, genesis_validators_root: [u8; 32]) -> ForkContext {
+        ForkContext::new(
+            self.fork_schedule().clone(),
+            self.slot_generator.slots_per_epoch(),
+            genesis_validators_root,
+        )
+        .with_subnet_counts(
+            self.slot_generator.attestation_subnets(),
+            self.slot_generator.sync_committee_subnets(),
         )
     }
 
-    // Occurs every slot
-    fn queue_slot_msgs(&mut self, current_slot: Slot) {
-        for msg_type in MsgType::iter() {
-            match msg_type {
-                MsgType::BeaconBlock => self.queued_messages.extend(
-                    self.slot_generator
-                        .get_blocks(current_slot, &self.validators)
-                        .map(|proposer| Message::BeaconBlock {
-                            proposer,
-                            slot: current_slot,
-                        }),
-                ),
-                MsgType::AggregateAndProofAttestation => self.queued_messages.extend(
-                    self.slot_generator
-                        .get_aggregates(current_slot, &self.validators)
-                        .map(
-                            |(aggregator, subnet)| Message::AggregateAndProofAttestation {
-                                aggregator,
-                                subnet,
-                                slot: current_slot,
-                            },
-                        ),
-                ),
-                MsgType::Attestation => self.queued_messages.extend(
-                    self.slot_generator
-                        .get_attestations(current_slot, &self.validators)
-                        .map(|(attester, subnet)| Message::Attestation {
-                            attester,
-                            subnet,
-                            slot: current_slot,
-                        }),
-                ),
-                MsgType::SignedContributionAndProof => self.queued_messages.extend(
-                    self.slot_generator
-                        .get_sync_committee_aggregates(current_slot, &self.validators)
-                        .map(|(validator, subnet)| Message::SignedContributionAndProof {
-                            validator,
-                            subnet,
-                            slot: current_slot,
-                        }),
-                ),
-                MsgType::SyncCommitteeMessage => self.queued_messages.extend(
-                    self.slot_generator
-                        .get_sync_committee_messages(current_slot, &self.validators)
-                        .map(|(validator, subnet)| Message::SyncCommitteeMessage {
-                            validator,
-                            subnet,
-                            slot: current_slot,
-                        }),
-                ),
-            }
+    /// SSZ payload of a message, laid out as in the fork of its slot and filled as set by the
+    /// payload mode. Payloads depend only on the generator's seed and the message, so seeded runs
+    /// produce the same payloads regardless of the order in which they are requested.
+    pub fn payload(&self, message: &Message) -> Vec<u8> {
+        let preimage = [
+            self.payload_seed.to_le_bytes().as_slice(),
+            &message.seed_bytes(),
+        ]
+        .concat();
+        let fork = self.slot_generator.fork_at(message.slot());
+        let mut rng = seeded_rng(&preimage);
+        match self.payload_mode {
+            PayloadMode::Zeroed => message.payload(fork, &mut rng),
+            PayloadMode::Realistic => message.realistic_payload(fork, &mut rng),
+        }
+    }
+
+    /// Payload of a message as sent on the wire, snappy compressed.
+    pub fn encoded_payload(&self, message: &Message) -> Vec<u8> {
+        sizes::encode_ssz_snappy(&self.payload(message))
+    }
+
+    /// Start of the slot as a duration since the slot clock's origin, in simulated time.
+    fn simulated_start_of(&self, slot: Slot) -> Duration {
+        let slots_since_genesis = slot
+            .as_u64()
+            .saturating_sub(self.slot_clock.genesis_slot().as_u64());
+        // Compressed time since the UNIX epoch can outgrow u64 nanoseconds, so the product is
+        // split back into seconds and nanoseconds.
+        let nanos_since_genesis = self.slot_duration().as_nanos() * u128::from(slots_since_genesis);
+        let since_genesis = Duration::new(
+            (nanos_since_genesis / NANOS_PER_SECOND)
+                .try_into()
+                .expect("simulated time fits in u64 seconds"),
+            (nanos_since_genesis % NANOS_PER_SECOND) as u32,
+        );
+        self.slot_clock
+            .genesis_duration()
+            .checked_add(since_genesis)
+            .expect("simulated time fits in u64 seconds")
+    }
+
+    /// Current time as a duration since the slot clock's origin, in simulated time.
+    fn simulated_now(&self) -> Duration {
+        let now = self
+            .slot_clock
+            .now_duration()
+            .expect("slot clock is unlikely to fail");
+        simulated_time(now, self.slot_clock.genesis_duration(), self.time_scale)
+            .expect("simulated time fits in u64 seconds")
+    }
+
+    /// Adds the timing information to a message being returned by the stream.
+    fn emit(&self, timed: TimedMessage) -> EmittedMessage {
+        let TimedMessage {
+            message,
+            phase,
+            offset,
+        } = timed;
+        let slot = message.slot();
+        let scheduled = self.simulated_start_of(slot) + offset;
+        EmittedMessage {
+            slot,
+            epoch: slot.epoch(self.slot_generator.slots_per_epoch()),
+            fork: self.slot_generator.fork_at(slot),
+            phase,
+            scheduled,
+            emitted: Instant::now(),
+            delay: self.simulated_now().saturating_sub(scheduled),
+            message,
         }
     }
 
-    // Occurs every 2/3 of a slot
-    fn queue_aggregate_msgs(&mut self, current_slot: Slot) {
-        for msg_type in MsgType::iter() {
-            match msg_type {
-                MsgType::BeaconBlock => {}
-                MsgType::AggregateAndProofAttestation => self.queued_messages.extend(
-                    self.slot_generator
-                        .get_aggregates(current_slot, &self.validators)
-                        .map(
-                            |(aggregator, subnet)| Message::AggregateAndProofAttestation {
-                                aggregator,
-                                subnet,
-                                slot: current_slot,
-                            },
-                        ),
-                ),
-                MsgType::Attestation => {}
-                MsgType::SignedContributionAndProof => self.queued_messages.extend(
-                    self.slot_generator
-                        .get_sync_committee_aggregates(current_slot, &self.validators)
-                        .map(|(validator, subnet)| Message::SignedContributionAndProof {
-                            validator,
-                            subnet,
-                            slot: current_slot,
-                        }),
-                ),
-                MsgType::SyncCommitteeMessage => {}
+    /// Start of the next phase to be processed, in simulated time.
+    fn next_phase_start(&self) -> Duration {
+        self.simulated_start_of(self.next_slot) + self.phase_offset(self.next_phase)
+    }
+
+    /// Queues the messages of the next phase and moves to the following one.
+    fn process_phase(&mut self) {
+        let (current_slot, current_phase) = (self.next_slot, self.next_phase);
+        let slot_start = self.simulated_start_of(current_slot);
+        let phase_offset = self.phase_offset(current_phase);
+        let epoch = current_slot.epoch(self.slot_generator.slots_per_epoch());
+        if self.duties.as_ref().map(EpochDuties::epoch) != Some(epoch) {
+            self.duties = Some(self.slot_generator.epoch_duties(epoch, &self.validators));
+        }
+        let duties = self
+            .duties
+            .as_ref()
+            .expect("duties of the epoch are computed");
+        for msg_type in MsgType::iter().filter(|msg_type| msg_type.phase() == current_phase) {
+            let jitter = self.jitter.get(&msg_type);
+            for message in duties.messages(msg_type, current_slot) {
+                let offset = phase_offset
+                    + jitter.map_or(Duration::ZERO, |jitter| jitter.sample(&mut self.rng));
+                let timed = TimedMessage {
+                    message,
+                    phase: current_phase,
+                    offset,
+                };
+                self.queued_messages
+                    .insert((slot_start + offset, self.queued_count), timed);
+                self.queued_count += 1;
             }
         }
+
+        match SlotPhase::iter().find(|phase| *phase > current_phase) {
+            Some(phase) => self.next_phase = phase,
+            None => {
+                self.next_phase = SlotPhase::Block;
+                self.next_slot += 1;
+            }
+        }
+    }
+
+    /// Messages sent by the local validators over a range of slots, in the order they are sent.
+    /// Does not depend on the slot clock nor on a tokio runtime. Messages are timed at the start
+    /// of their phase, without jitter.
+    pub fn messages_for_slots(
+        &self,
+        slots: Range<Slot>,
+    ) -> impl Iterator<Item = TimedMessage> + '_ {
+        let slots_per_epoch = self.slot_generator.slots_per_epoch();
+        (slots.start.as_u64()..slots.end.as_u64())
+            .map(Slot::new)
+            .scan(None, move |duties: &mut Option<EpochDuties>, slot| {
+                let epoch = slot.epoch(slots_per_epoch);
+                if duties.as_ref().map(EpochDuties::epoch) != Some(epoch) {
+                    *duties = Some(self.slot_generator.epoch_duties(epoch, &self.validators));
+                }
+                let duties = duties.as_ref().expect("duties of the epoch are computed");
+                let messages: Vec<_> = SlotPhase::iter()
+                    .flat_map(|phase| {
+                        let offset = self.phase_offset(phase);
+                        MsgType::iter()
+                            .filter(move |msg_type| msg_type.phase() == phase)
+                            .flat_map(|msg_type| duties.messages(msg_type, slot))
+                            .map(move |message| TimedMessage {
+                                message,
+                                phase,
+                                offset,
+                            })
+                    })
+                    .collect();
+                Some(messages)
+            })
+            .flatten()
     }
 }
 
-impl Stream for Generator {
-    type Item = Message;
+impl<C: SlotClock + Unpin> Stream for Generator<C> {
+    type Item = EmittedMessage;
 
     fn poll_next(
-        mut self: Pin<&mut Self>,
+        self: Pin<&mut Self>,
         cx: &mut std::task::Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        // If there were any messages remaining from the current slot, return them.
-        if let Some(msg) = self.queued_messages.pop_front() {
-            return Poll::Ready(Some(msg));
-        }
+        let this = self.get_mut();
+        loop {
+            // Process every phase the clock has moved past, in order.
+            let now = this.simulated_now();
+            let next_phase_start = this.next_phase_start();
+            if next_phase_start <= now {
+                this.process_phase();
+                continue;
+            }
 
-        if self.interval.poll_tick(cx).is_ready() {
-            self.interval_count += 1;
-            if self.interval_count == 3 {
-                self.interval_count = 0;
-                let current_slot = self.slot_clock.now().expect("Slot exists");
-                self.queue_slot_msgs(current_slot);
-            } else if self.interval_count == 2 {
-                // Aggregates get sent 2/3 of the way through the slot
-                let current_slot = self.slot_clock.now().expect("Slot exists");
-                self.queue_aggregate_msgs(current_slot);
+            // Return the earliest queued message if it's due.
+            let mut next_event = next_phase_start;
+            if let Some(entry) = this.queued_messages.first_entry() {
+                let (due, _) = *entry.key();
+                if due <= now {
+                    let msg = entry.remove();
+                    return Poll::Ready(Some(this.emit(msg)));
+                }
+                next_event = next_event.min(due);
+            }
+            let mut wait = (next_event - now) / this.time_scale;
+            if let Some(clock_poll_interval) = this.clock_poll_interval {
+                wait = wait.min(clock_poll_interval);
             }
-        }
 
-        // If there were any messages remaining from the current slot, return them.
-        if let Some(msg) = self.queued_messages.pop_front() {
-            return Poll::Ready(Some(msg));
+            // Wait for the next phase or message. The clock is checked again once the timer fires,
+            // since clocks not based on system time might not have moved forward by then, or might
+            // have moved past it.
+            let deadline = tokio::time::Instant::now() + wait;
+            let sleep = this
+                .sleep
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+            sleep.as_mut().reset(deadline);
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
         }
-
-        Poll::Pending
     }
 }
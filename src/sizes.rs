@@ -1,32 +1,158 @@
-use crate::Message;
+use crate::{fork::Fork, spec, topic::GossipTopic, Message};
+use eth2_hashing::hash;
 use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+/// SSZ size of a `BlobSidecar`.
+const BLOB_SIDECAR_SIZE: usize = 8 + 131_072 + 48 + 48 + 208 + 17 * 32;
+/// SSZ size of a `DataColumnSidecar` without blobs: index, offsets of its three lists, signed
+/// block header and commitments inclusion proof.
+const DATA_COLUMN_SIDECAR_BASE_SIZE: usize = 8 + 3 * 4 + 208 + 4 * 32;
+/// SSZ size each blob adds to a `DataColumnSidecar`: its cell, KZG commitment and proof.
+const DATA_COLUMN_SIDECAR_SIZE_PER_BLOB: usize = 2048 + 48 + 48;
+/// SSZ size of a `SingleAttestation`: committee and attester indices, attestation data and
+/// signature. Sent on the attestation subnets since Electra.
+const SINGLE_ATTESTATION_SIZE: usize = 8 + 8 + 128 + 96;
+/// SSZ size of a `SignedVoluntaryExit`.
+const SIGNED_VOLUNTARY_EXIT_SIZE: usize = 8 + 8 + 96;
+/// SSZ size of a `ProposerSlashing`: two signed block headers.
+const PROPOSER_SLASHING_SIZE: usize = 2 * 208;
+/// SSZ size of a `SignedBLSToExecutionChange`.
+const SIGNED_BLS_TO_EXECUTION_CHANGE_SIZE: usize = 8 + 48 + 20 + 96;
+/// SSZ size of a `BeaconBlockHeader`.
+const BEACON_BLOCK_HEADER_SIZE: usize = 112;
+/// SSZ size of a `SyncAggregate`: participation bits and signature.
+const SYNC_AGGREGATE_SIZE: usize = 512 / 8 + 96;
+/// Extra data of execution payload headers. Block builders fill most of the 32 bytes allowed.
+const EXTRA_DATA_SIZE: usize = 32;
+
+/// Size to which snappy compresses a run of zeros, relative to its length: each copy of up to 64
+/// bytes takes a 3 byte tag.
+const SNAPPY_ZERO_RUN_RATIO: f64 = 3.0 / 64.0;
+/// Bytes snappy spends on the length and tags of a payload made of a run of zeros and random
+/// bytes.
+const SNAPPY_TAGS_SIZE: usize = 8;
+
+/// How message payloads are filled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadMode {
+    /// Zeros, except for a few random bytes making each payload unique. Sized as the message,
+    /// but compress to a small fraction of it.
+    #[default]
+    Zeroed,
+    /// Zeros followed by random bytes, in the proportion SSZ encoded messages have them. Sized so
+    /// that the snappy compressed size follows the sizes measured on the wire.
+    Realistic,
+}
+
+/// Encodes an SSZ payload for gossip, compressing it with snappy's block format.
+pub fn encode_ssz_snappy(ssz: &[u8]) -> Vec<u8> {
+    snap::raw::Encoder::new()
+        .compress_vec(ssz)
+        .expect("payloads are within snappy's size limit")
+}
+
+/// SSZ size of a `LightClientHeader` of the fork. Since Capella it carries the execution payload
+/// header, with extra data of [`EXTRA_DATA_SIZE`], and its inclusion proof.
+fn light_client_header_size(fork: Fork) -> usize {
+    let execution_header_size = match fork {
+        Fork::Phase0 | Fork::Altair | Fork::Bellatrix => return BEACON_BLOCK_HEADER_SIZE,
+        Fork::Capella => 568,
+        // Blob gas fields.
+        Fork::Deneb | Fork::Electra | Fork::Fulu => 568 + 16,
+    };
+    BEACON_BLOCK_HEADER_SIZE + 4 + execution_header_size + EXTRA_DATA_SIZE + 4 * 32
+}
+
+/// Size of the offset of each light client header within an update. Headers are variable in
+/// size since Capella.
+fn light_client_header_offset(fork: Fork) -> usize {
+    if fork >= Fork::Capella {
+        4
+    } else {
+        0
+    }
+}
 
 impl Message {
-    // Tested from live mainnet results
-    pub fn payload(&self, rng: &mut rand::rngs::SmallRng) -> Vec<u8> {
-        let mut message = match self {
+    /// Size of the message as laid out in the fork. Blocks, attestations, aggregates and sync
+    /// committee messages vary within the ranges seen on mainnet, the other messages follow their
+    /// SSZ layout.
+    fn size(&self, fork: Fork, rng: &mut ChaCha8Rng) -> usize {
+        match self {
             Message::BeaconBlock { .. } => {
-                let bytes: u32 = rng.gen_range(30_000..70_000);
-                vec![0; bytes as usize]
-                // Thread is some random values to make the payloads distinct
+                // Execution payloads and their transactions make up most of the block since
+                // Bellatrix.
+                let bytes: u32 = if fork >= Fork::Bellatrix {
+                    rng.gen_range(30_000..70_000)
+                } else {
+                    rng.gen_range(5_000..20_000)
+                };
+                bytes as usize
+            }
+            Message::BlobSidecar { .. } => {
+                // Fixed size: the 128 KiB blob plus its index, KZG commitment and proof, signed
+                // block header and commitment inclusion proof.
+                BLOB_SIDECAR_SIZE
+            }
+            Message::DataColumnSidecar { blob_count, .. } => {
+                DATA_COLUMN_SIDECAR_BASE_SIZE
+                    + DATA_COLUMN_SIDECAR_SIZE_PER_BLOB * *blob_count as usize
             }
             Message::AggregateAndProofAttestation { .. } => {
                 let bytes: u32 = rng.gen_range(500..550);
-                vec![0; bytes as usize]
+                // Committee bits of the attestation since Electra.
+                let committee_bits = if fork >= Fork::Electra { 8 } else { 0 };
+                bytes as usize + committee_bits
             }
             Message::Attestation { .. } => {
-                let bytes: u32 = rng.gen_range(200..310);
-                vec![0; bytes as usize]
+                if fork >= Fork::Electra {
+                    SINGLE_ATTESTATION_SIZE
+                } else {
+                    let bytes: u32 = rng.gen_range(200..310);
+                    bytes as usize
+                }
             }
             Message::SignedContributionAndProof { .. } => {
                 let bytes: u32 = rng.gen_range(410..430);
-                vec![0; bytes as usize]
+                bytes as usize
             }
             Message::SyncCommitteeMessage { .. } => {
                 let bytes: u32 = rng.gen_range(190..210);
-                vec![0; bytes as usize]
+                bytes as usize
             }
-        };
+            Message::LightClientFinalityUpdate { .. } => {
+                // Attested and finalized headers, finality branch, sync aggregate and signature
+                // slot. The finality branch is a level deeper since Electra.
+                let finality_branch = if fork >= Fork::Electra { 7 } else { 6 };
+                2 * light_client_header_offset(fork)
+                    + 2 * light_client_header_size(fork)
+                    + finality_branch * 32
+                    + SYNC_AGGREGATE_SIZE
+                    + 8
+            }
+            Message::LightClientOptimisticUpdate { .. } => {
+                // Attested header, sync aggregate and signature slot.
+                light_client_header_offset(fork)
+                    + light_client_header_size(fork)
+                    + SYNC_AGGREGATE_SIZE
+                    + 8
+            }
+            Message::VoluntaryExit { .. } => SIGNED_VOLUNTARY_EXIT_SIZE,
+            Message::ProposerSlashing { .. } => PROPOSER_SLASHING_SIZE,
+            Message::AttesterSlashing { .. } => {
+                // Two indexed attestations, each with the attesting indices of a committee, its
+                // attestation data and signature.
+                let attesting_indices: usize = rng.gen_range(100..600);
+                8 + 2 * (4 + 8 * attesting_indices + 128 + 96)
+            }
+            Message::BlsToExecutionChange { .. } => SIGNED_BLS_TO_EXECUTION_CHANGE_SIZE,
+        }
+    }
+
+    /// Payload of the message as laid out in the fork.
+    pub fn payload(&self, fork: Fork, rng: &mut ChaCha8Rng) -> Vec<u8> {
+        let mut message = vec![0; self.size(fork, rng)];
 
         // Ranomize the first 8 bits to make sure the message is unique.
         let first_bytes = &mut message[0..8];
@@ -34,4 +160,59 @@ impl Message {
 
         message
     }
+
+    /// Whether the size of [`Message::payload`] is the size measured on the wire, after
+    /// compression, instead of the SSZ size.
+    fn is_sized_on_wire(&self, fork: Fork) -> bool {
+        match self {
+            Message::BeaconBlock { .. }
+            | Message::AggregateAndProofAttestation { .. }
+            | Message::SignedContributionAndProof { .. }
+            | Message::SyncCommitteeMessage { .. } => true,
+            Message::Attestation { .. } => fork < Fork::Electra,
+            _ => false,
+        }
+    }
+
+    /// Share of the SSZ encoded message made of bytes that look random. Signatures, roots and
+    /// keys make up most of consensus messages, while execution payloads have some redundancy.
+    fn entropy(&self) -> f64 {
+        match self {
+            Message::BeaconBlock { .. } => 0.5,
+            _ => 0.9,
+        }
+    }
+
+    /// Payload of the message as laid out in the fork, with zeros followed by random bytes. Its
+    /// snappy compressed size is the size of [`Message::payload`] for messages measured on the
+    /// wire, and a realistic fraction of the SSZ size for the rest.
+    pub fn realistic_payload(&self, fork: Fork, rng: &mut ChaCha8Rng) -> Vec<u8> {
+        let mut size = self.size(fork, rng);
+        let entropy = self.entropy();
+        if self.is_sized_on_wire(fork) {
+            // Pad with zeros so that the random bytes, the compressed zeros and the snappy tags
+            // add up to the measured size.
+            let wire_size = size.saturating_sub(SNAPPY_TAGS_SIZE) as f64;
+            size = (wire_size / (entropy + SNAPPY_ZERO_RUN_RATIO * (1.0 - entropy))) as usize;
+        }
+        let mut payload = vec![0; size];
+        let random_bytes = (size as f64 * entropy) as usize;
+        rng.fill(&mut payload[size - random_bytes..]);
+        payload
+    }
+
+    /// Gossipsub message-id of a payload published on the topic, as computed by consensus clients
+    /// since Altair: the first 20 bytes of the hash of the valid snappy domain, the topic's length
+    /// and the topic, and the decompressed payload. Takes the payload uncompressed, as returned by
+    /// [`Message::payload`].
+    pub fn message_id(payload: &[u8], topic: &GossipTopic) -> [u8; 20] {
+        let topic = topic.to_string();
+        let mut preimage = spec::MESSAGE_DOMAIN_VALID_SNAPPY.to_vec();
+        preimage.extend((topic.len() as u64).to_le_bytes());
+        preimage.extend(topic.as_bytes());
+        preimage.extend(payload);
+        hash(&preimage)[..20]
+            .try_into()
+            .expect("hashes are 32 bytes long")
+    }
 }
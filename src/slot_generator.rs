@@ -1,6 +1,20 @@
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
 
+use rand::{seq::index, Rng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, Poisson};
 use slot_clock::Slot;
+use strum::IntoEnumIterator;
+use swap_or_not_shuffle::shuffle_list;
+use types::Epoch;
+
+use crate::{
+    fork::{Fork, ForkSchedule},
+    seeded_rng, spec, Message, MsgType,
+};
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
 pub struct ValId(pub u64);
@@ -36,6 +50,316 @@ impl std::fmt::Debug for Subnet {
     }
 }
 
+/// How validators are assigned to attestation committees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DutyMode {
+    /// Validators are dealt across the slots and committees of the epoch by index, rotating every
+    /// epoch.
+    #[default]
+    Simple,
+    /// Validators are shuffled into committees with the beacon chain's swap-or-not shuffle and a
+    /// RANDAO-like seed, and committees are mapped to subnets as in
+    /// `compute_subnet_for_attestation`.
+    Spec,
+}
+
+/// How the proposer of each slot is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProposerSelection {
+    /// Validators propose in turns, by index.
+    #[default]
+    RoundRobin,
+    /// Proposers are sampled with a chance weighted by their effective balance, as in
+    /// `compute_proposer_index`.
+    BalanceWeighted,
+}
+
+/// How aggregators are chosen among the members of an attestation committee or sync subnet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AggregatorSelection {
+    /// The first `target_aggregators` members of each committee or subnet aggregate.
+    #[default]
+    Fixed,
+    /// Each member aggregates with probability `1 / max(1, committee_len / target_aggregators)`,
+    /// as in the spec's `is_aggregator` and `is_sync_committee_aggregator`. The number of
+    /// aggregators varies from slot to slot and can be zero.
+    Modulo,
+}
+
+/// Number of blobs carried by each block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobCount {
+    /// Every block carries the same number of blobs.
+    Fixed(u64),
+    /// The number of blobs of each block is drawn uniformly from `[0, max]`.
+    Uniform { max: u64 },
+}
+
+impl Default for BlobCount {
+    fn default() -> Self {
+        BlobCount::Fixed(0)
+    }
+}
+
+impl BlobCount {
+    /// Largest number of blobs a block can carry.
+    pub fn max(&self) -> u64 {
+        match *self {
+            BlobCount::Fixed(count) => count,
+            BlobCount::Uniform { max } => max,
+        }
+    }
+
+    pub fn sample(&self, rng: &mut ChaCha8Rng) -> u64 {
+        match *self {
+            BlobCount::Fixed(count) => count,
+            BlobCount::Uniform { max } => rng.gen_range(0..=max),
+        }
+    }
+}
+
+/// How the blobs of a block are distributed over gossip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobDistribution {
+    /// A sidecar per blob, as since Deneb.
+    BlobSidecars,
+    /// A sidecar per column of the extended blobs, carrying a cell of each blob, as in PeerDAS.
+    DataColumns,
+}
+
+/// How the sync committee of each period is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncCommitteeSelection {
+    /// Consecutive validators form the committee, rotating every period. Each member belongs to
+    /// a single subnet.
+    #[default]
+    Rotating,
+    /// Members are sampled with replacement with a chance weighted by their effective balance, as
+    /// in `get_next_sync_committee_indices`. A validator can hold several positions of the
+    /// committee, in the same or different subnets, and sends a message for each of them.
+    BalanceWeighted,
+}
+
+/// Sync committee of a period.
+struct SyncCommittee {
+    period: u64,
+    /// Validator at each position of the committee. Subnets are contiguous ranges of it.
+    members: Vec<ValId>,
+}
+
+/// Position of a validator in the sync committee.
+struct SyncDuty {
+    val_id: ValId,
+    subnet: Subnet,
+    /// Position of the validator within the subnet.
+    position: u64,
+    /// Whether this is the first position of the validator in the subnet.
+    first_in_subnet: bool,
+}
+
+/// Attestation committees of an epoch.
+struct EpochCommittees {
+    epoch: u64,
+    /// Number of committees in each slot of the epoch.
+    committees_per_slot: u64,
+    /// Number of slots in the epoch.
+    slots_per_epoch: u64,
+    /// Position of each validator in the shuffled list. Committees are contiguous ranges of it.
+    positions: Vec<usize>,
+}
+
+/// Committee assignment of a validator within an epoch.
+struct AttesterDuty {
+    slot_in_epoch: u64,
+    committee_index: u64,
+    /// Position of the validator within the committee.
+    position: usize,
+    /// Number of members of the committee.
+    committee_len: usize,
+}
+
+impl EpochCommittees {
+    fn new(
+        epoch: u64,
+        slots_per_epoch: u64,
+        committees_per_slot: u64,
+        positions: Vec<usize>,
+    ) -> Self {
+        Self {
+            epoch,
+            committees_per_slot,
+            slots_per_epoch,
+            positions,
+        }
+    }
+
+    fn duty(&self, val_id: ValId) -> AttesterDuty {
+        let index_count = self.positions.len();
+        let committee_count = self.committees_per_slot * self.slots_per_epoch;
+        let position = self.positions[*val_id as usize];
+        // Last committee starting at or before the validator's position.
+        let committee = ((position as u64 + 1) * committee_count - 1) / index_count as u64;
+        let range = spec::committee_range(index_count, committee, committee_count);
+        AttesterDuty {
+            slot_in_epoch: committee / self.committees_per_slot,
+            committee_index: committee % self.committees_per_slot,
+            position: position - range.start,
+            committee_len: range.len(),
+        }
+    }
+}
+
+/// Duties of a set of validators over an epoch. Computed once per epoch so that generating the
+/// messages of a slot only visits the validators with a duty in it.
+pub struct EpochDuties {
+    epoch: Epoch,
+    /// First slot of the epoch.
+    start_slot: Slot,
+    /// Proposer of each slot, if among the validators.
+    proposers: Vec<Option<ValId>>,
+    /// Fork active in the epoch.
+    fork: Fork,
+    /// Number of blobs of each slot's block.
+    blob_counts: Vec<u64>,
+    /// Attesters of each slot and their subnets.
+    attesters: Vec<Vec<(ValId, Subnet)>>,
+    /// Attestation aggregators of each slot and their subnets.
+    aggregators: Vec<Vec<(ValId, Subnet)>>,
+    /// Sync committee positions held by the validators. The same in every slot of the epoch.
+    sync_members: Vec<(ValId, Subnet)>,
+    /// Sync committee aggregators of each slot and their subnets.
+    sync_aggregators: Vec<Vec<(ValId, Subnet)>>,
+    /// Operations sent in each slot, by type and validator.
+    operations: Vec<Vec<(MsgType, ValId)>>,
+    /// Whether light client updates are sent every slot.
+    light_client_updates: bool,
+}
+
+impl EpochDuties {
+    pub fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+
+    /// Messages of the given type sent by the validators in the slot, which must belong to the
+    /// epoch.
+    pub fn messages(
+        &self,
+        msg_type: MsgType,
+        slot: Slot,
+    ) -> Box<dyn Iterator<Item = Message> + '_> {
+        assert!(
+            slot >= self.start_slot && slot < self.start_slot + self.attesters.len() as u64,
+            "slot is not in the epoch"
+        );
+        let index = (slot - self.start_slot).as_u64() as usize;
+        if !msg_type.is_sent_in(self.fork) {
+            return Box::new(std::iter::empty());
+        }
+        match msg_type {
+            MsgType::BeaconBlock => Box::new(
+                self.proposers[index]
+                    .into_iter()
+                    .map(move |proposer| Message::BeaconBlock { proposer, slot }),
+            ),
+            MsgType::BlobSidecar => {
+                let blob_count = self.blob_counts[index];
+                let subnet_count = self.fork.blob_sidecar_subnet_count();
+                Box::new(self.proposers[index].into_iter().flat_map(move |proposer| {
+                    (0..blob_count).map(move |index| Message::BlobSidecar {
+                        proposer,
+                        index,
+                        subnet: Subnet(spec::compute_subnet_for_blob_sidecar(index, subnet_count)),
+                        slot,
+                    })
+                }))
+            }
+            MsgType::DataColumnSidecar => {
+                let blob_count = self.blob_counts[index];
+                let column_count = if blob_count > 0 {
+                    spec::NUMBER_OF_COLUMNS
+                } else {
+                    0
+                };
+                Box::new(self.proposers[index].into_iter().flat_map(move |proposer| {
+                    (0..column_count).map(move |index| Message::DataColumnSidecar {
+                        proposer,
+                        index,
+                        subnet: Subnet(spec::compute_subnet_for_data_column_sidecar(index)),
+                        blob_count,
+                        slot,
+                    })
+                }))
+            }
+            MsgType::AggregateAndProofAttestation => Box::new(self.aggregators[index].iter().map(
+                move |(aggregator, subnet)| Message::AggregateAndProofAttestation {
+                    aggregator: *aggregator,
+                    subnet: *subnet,
+                    slot,
+                },
+            )),
+            MsgType::Attestation => {
+                Box::new(self.attesters[index].iter().map(move |(attester, subnet)| {
+                    Message::Attestation {
+                        attester: *attester,
+                        subnet: *subnet,
+                        slot,
+                    }
+                }))
+            }
+            MsgType::SignedContributionAndProof => Box::new(
+                self.sync_aggregators[index]
+                    .iter()
+                    .map(
+                        move |(validator, subnet)| Message::SignedContributionAndProof {
+                            validator: *validator,
+                            subnet: *subnet,
+                            slot,
+                        },
+                    ),
+            ),
+            MsgType::SyncCommitteeMessage => {
+                Box::new(self.sync_members.iter().map(move |(validator, subnet)| {
+                    Message::SyncCommitteeMessage {
+                        validator: *validator,
+                        subnet: *subnet,
+                        slot,
+                    }
+                }))
+            }
+            MsgType::LightClientFinalityUpdate => Box::new(
+                self.light_client_updates
+                    .then_some(Message::LightClientFinalityUpdate { slot })
+                    .into_iter(),
+            ),
+            MsgType::LightClientOptimisticUpdate => Box::new(
+                self.light_client_updates
+                    .then_some(Message::LightClientOptimisticUpdate { slot })
+                    .into_iter(),
+            ),
+            MsgType::VoluntaryExit
+            | MsgType::ProposerSlashing
+            | MsgType::AttesterSlashing
+            | MsgType::BlsToExecutionChange => Box::new(
+                self.operations[index]
+                    .iter()
+                    .filter(move |(operation, _)| *operation == msg_type)
+                    .map(move |(_, validator)| operation_message(msg_type, *validator, slot)),
+            ),
+        }
+    }
+}
+
+/// Message of an operation of the given type.
+fn operation_message(msg_type: MsgType, validator: ValId, slot: Slot) -> Message {
+    match msg_type {
+        MsgType::VoluntaryExit => Message::VoluntaryExit { validator, slot },
+        MsgType::ProposerSlashing => Message::ProposerSlashing { validator, slot },
+        MsgType::AttesterSlashing => Message::AttesterSlashing { validator, slot },
+        MsgType::BlsToExecutionChange => Message::BlsToExecutionChange { validator, slot },
+        _ => unreachable!("{msg_type} is not an operation"),
+    }
+}
+
 pub struct SlotGenerator {
     /// Epoch definition.
     slots_per_epoch: u64,
@@ -45,13 +369,41 @@ pub struct SlotGenerator {
     sync_subnet_size: u64,
     /// Number of subcommittees to split members of the sync committee.
     sync_committee_subnets: u64,
-    /// Number of validators to designate as aggregators in the sync committee and attestation
-    /// subnets.
+    /// Number of validators to designate as aggregators in each attestation committee.
     target_aggregators: u64,
+    /// Number of validators to designate as aggregators in each sync subnet.
+    target_sync_aggregators: u64,
     /// Number of validators in the network.
     total_validators: u64,
-    /// GCD(total_validators, attestation_subnets) == 1.
-    att_subnets_is_relative: bool,
+    /// Number of attestation committees in each slot.
+    committees_per_slot: u64,
+    /// How validators are assigned to attestation committees.
+    duty_mode: DutyMode,
+    /// How aggregators are chosen among the members of a committee or sync subnet.
+    aggregator_selection: AggregatorSelection,
+    /// How the proposer of each slot is chosen.
+    proposer_selection: ProposerSelection,
+    /// How the sync committee of each period is chosen.
+    sync_committee_selection: SyncCommitteeSelection,
+    /// Number of blobs carried by each block.
+    blob_count: BlobCount,
+    /// Mean number of operations of each type sent by the validators per epoch.
+    operation_rates: HashMap<MsgType, f64>,
+    /// Epochs at which forks activate, turning message types on and off.
+    fork_schedule: ForkSchedule,
+    /// Whether the node serves light clients, sending them an update of each kind every slot.
+    serve_light_clients: bool,
+    /// Effective balance of each validator, in Gwei. Validators without one hold
+    /// [`spec::MAX_EFFECTIVE_BALANCE`].
+    effective_balances: Vec<u64>,
+    /// Seed from which RANDAO-like mixes are derived.
+    seed: u64,
+    /// Committees of the last epoch requested.
+    committees: Mutex<Option<Arc<EpochCommittees>>>,
+    /// Sync committee of the last period requested.
+    sync_committee: Mutex<Option<Arc<SyncCommittee>>>,
+    /// Duties of the last epoch and validators requested through the per-slot getters.
+    duties: Mutex<Option<(BTreeSet<ValId>, Arc<EpochDuties>)>>,
 }
 
 impl SlotGenerator {
@@ -63,112 +415,649 @@ impl SlotGenerator {
         target_aggregators: u64,
         total_validators: u64,
     ) -> Self {
-        fn gcd(mut a: u64, mut b: u64) -> u64 {
-            while b > 0 {
-                (a, b) = (b, a % b);
-            }
-            a
-        }
-
-        let att_subnets_is_relative = gcd(attestation_subnets, slots_per_epoch) == 1;
+        assert!(
+            attestation_subnets > 0,
+            "attestation_subnets must be positive"
+        );
+        // There is at most one committee per subnet in each slot, as on mainnet where
+        // MAX_COMMITTEES_PER_SLOT matches the number of attestation subnets.
+        let committees_per_slot =
+            spec::committee_count_per_slot(total_validators, slots_per_epoch, attestation_subnets);
         Self {
             slots_per_epoch,
             attestation_subnets,
             sync_subnet_size,
             sync_committee_subnets,
             target_aggregators,
+            target_sync_aggregators: target_aggregators,
             total_validators,
-            att_subnets_is_relative,
+            committees_per_slot,
+            duty_mode: DutyMode::default(),
+            aggregator_selection: AggregatorSelection::default(),
+            proposer_selection: ProposerSelection::default(),
+            sync_committee_selection: SyncCommitteeSelection::default(),
+            blob_count: BlobCount::default(),
+            operation_rates: HashMap::new(),
+            fork_schedule: ForkSchedule::default(),
+            serve_light_clients: false,
+            effective_balances: Vec::new(),
+            seed: 0,
+            committees: Mutex::new(None),
+            sync_committee: Mutex::new(None),
+            duties: Mutex::new(None),
         }
     }
 
-    pub fn get_blocks(&self, slot: Slot, validators: &HashSet<ValId>) -> Option<ValId> {
-        let proposer = ValId(slot.as_u64() % self.total_validators);
-        validators.contains(&proposer).then_some(proposer)
+    /// Sets how validators are assigned to attestation committees.
+    pub fn with_duty_mode(mut self, duty_mode: DutyMode) -> Self {
+        self.duty_mode = duty_mode;
+        self
+    }
+
+    /// Sets how aggregators are chosen among the members of a committee.
+    pub fn with_aggregator_selection(mut self, aggregator_selection: AggregatorSelection) -> Self {
+        self.aggregator_selection = aggregator_selection;
+        self
+    }
+
+    /// Sets how the proposer of each slot is chosen.
+    pub fn with_proposer_selection(mut self, proposer_selection: ProposerSelection) -> Self {
+        self.proposer_selection = proposer_selection;
+        self
+    }
+
+    /// Sets the number of validators to designate as aggregators in each sync subnet. Defaults to
+    /// the attestation target.
+    pub fn with_target_sync_aggregators(mut self, target_sync_aggregators: u64) -> Self {
+        self.target_sync_aggregators = target_sync_aggregators;
+        self
+    }
+
+    /// Sets how the sync committee of each period is chosen.
+    pub fn with_sync_committee_selection(
+        mut self,
+        sync_committee_selection: SyncCommitteeSelection,
+    ) -> Self {
+        self.sync_committee_selection = sync_committee_selection;
+        self
+    }
+
+    /// Sets the number of blobs carried by each block.
+    pub fn with_blob_count(mut self, blob_count: BlobCount) -> Self {
+        self.blob_count = blob_count;
+        self
+    }
+
+    /// Sets the mean number of operations of each type sent by the validators per epoch. Rates
+    /// must be finite and not negative.
+    pub fn with_operation_rates(mut self, operation_rates: HashMap<MsgType, f64>) -> Self {
+        assert!(
+            operation_rates
+                .values()
+                .all(|rate| rate.is_finite() && *rate >= 0.0),
+            "operation rates must be finite and not negative"
+        );
+        self.operation_rates = operation_rates;
+        self
+    }
+
+    /// Sets the epochs at which forks activate.
+    pub fn with_fork_schedule(mut self, fork_schedule: ForkSchedule) -> Self {
+        self.fork_schedule = fork_schedule;
+        self
+    }
+
+    /// Sets whether the node serves light clients.
+    pub fn with_light_client_server(mut self, serve_light_clients: bool) -> Self {
+        self.serve_light_clients = serve_light_clients;
+        self
+    }
+
+    /// Sets the effective balance of each validator, in Gwei.
+    pub fn with_effective_balances(mut self, effective_balances: Vec<u64>) -> Self {
+        self.effective_balances = effective_balances;
+        self
+    }
+
+    /// Sets the seed from which RANDAO-like mixes and selection proofs are derived.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Effective balance of the validator, in Gwei.
+    fn effective_balance(&self, val_id: usize) -> u64 {
+        self.effective_balances
+            .get(val_id)
+            .copied()
+            .unwrap_or(spec::MAX_EFFECTIVE_BALANCE)
+    }
+
+    /// Committees of the epoch. Cached until a different epoch is requested.
+    fn epoch_committees(&self, epoch: u64) -> Arc<EpochCommittees> {
+        let mut cached = self.committees.lock().expect("lock is not poisoned");
+        if let Some(committees) = cached
+            .as_ref()
+            .filter(|committees| committees.epoch == epoch)
+        {
+            return committees.clone();
+        }
+
+        let positions = match self.duty_mode {
+            DutyMode::Simple => {
+                // shake the val ids using the epoch, and deal them across the slots and then the
+                // committees of the epoch, so that consecutive validators attest in different
+                // slots.
+                let committees = self.slots_per_epoch * self.committees_per_slot;
+                let mut order: Vec<u64> = (0..self.total_validators).collect();
+                order.sort_unstable_by_key(|val_id| {
+                    let shaked_val_id = val_id.overflowing_add(epoch).0 % self.total_validators;
+                    (
+                        shaked_val_id % self.slots_per_epoch,
+                        shaked_val_id / self.slots_per_epoch % self.committees_per_slot,
+                        shaked_val_id / committees,
+                    )
+                });
+                let mut positions = vec![0; order.len()];
+                for (position, val_id) in order.into_iter().enumerate() {
+                    positions[val_id as usize] = position;
+                }
+                positions
+            }
+            DutyMode::Spec => {
+                let seed = spec::get_seed(self.seed, epoch, spec::DOMAIN_BEACON_ATTESTER);
+                let active_validators = (0..self.total_validators as usize).collect();
+                let shuffling =
+                    shuffle_list(active_validators, spec::SHUFFLE_ROUND_COUNT, &seed, false)
+                        .expect("validator count is within the shuffle limits");
+                let mut positions = vec![0; shuffling.len()];
+                for (position, val_id) in shuffling.into_iter().enumerate() {
+                    positions[val_id] = position;
+                }
+                positions
+            }
+        };
+        let committees = Arc::new(EpochCommittees::new(
+            epoch,
+            self.slots_per_epoch,
+            self.committees_per_slot,
+            positions,
+        ));
+        *cached = Some(committees.clone());
+        committees
     }
 
-    pub fn get_attestations<'a>(
-        &'a self,
+    /// Balance weighted sync committee of the period. Cached until a different period is
+    /// requested.
+    fn sync_committee(&self, period: u64) -> Arc<SyncCommittee> {
+        let mut cached = self.sync_committee.lock().expect("lock is not poisoned");
+        if let Some(committee) = cached
+            .as_ref()
+            .filter(|committee| committee.period == period)
+        {
+            return committee.clone();
+        }
+
+        let epoch = period * crate::EPOCHS_PER_SYNC_COMMITTEE_PERIOD;
+        let seed = spec::get_seed(self.seed, epoch, spec::DOMAIN_SYNC_COMMITTEE);
+        let members = spec::sample_by_balance(&seed, self.total_validators as usize, |val_id| {
+            self.effective_balance(val_id)
+        })
+        .take((self.sync_subnet_size * self.sync_committee_subnets) as usize)
+        .map(|val_id| ValId(val_id as u64))
+        .collect();
+        let committee = Arc::new(SyncCommittee { period, members });
+        *cached = Some(committee.clone());
+        committee
+    }
+
+    /// Sync committee positions held by the validators during the period.
+    fn sync_duties(&self, period: u64, validators: &BTreeSet<ValId>) -> Vec<SyncDuty> {
+        match self.sync_committee_selection {
+            SyncCommitteeSelection::Rotating => validators
+                .iter()
+                .filter_map(|val_id| {
+                    // shake the val id using the sync_committee_period and move it back to
+                    // the validator ids range.
+                    let shaked_val_id = val_id.overflowing_add(period).0 % self.total_validators;
+                    let sync_committee_size = self.sync_subnet_size * self.sync_committee_subnets;
+                    let in_commitee = shaked_val_id / sync_committee_size == 0;
+                    in_commitee.then(|| SyncDuty {
+                        val_id: *val_id,
+                        subnet: Subnet(shaked_val_id % self.sync_committee_subnets),
+                        position: shaked_val_id / self.sync_committee_subnets,
+                        first_in_subnet: true,
+                    })
+                })
+                .collect(),
+            SyncCommitteeSelection::BalanceWeighted => {
+                let committee = self.sync_committee(period);
+                committee
+                    .members
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, val_id)| validators.contains(val_id))
+                    .map(|(index, val_id)| {
+                        let position = index as u64 % self.sync_subnet_size;
+                        let subnet_start = index - position as usize;
+                        SyncDuty {
+                            val_id: *val_id,
+                            subnet: Subnet(index as u64 / self.sync_subnet_size),
+                            position,
+                            first_in_subnet: !committee.members[subnet_start..index]
+                                .contains(val_id),
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn committee_subnet(&self, slot_in_epoch: u64, committee_index: u64) -> Subnet {
+        Subnet(spec::compute_subnet_for_attestation(
+            self.committees_per_slot,
+            slot_in_epoch,
+            committee_index,
+            self.attestation_subnets,
+        ))
+    }
+
+    /// Whether the validator at the given position of a committee aggregates it in the slot.
+    fn is_attestation_aggregator(
+        &self,
+        val_id: ValId,
         slot: Slot,
-        validators: &'a HashSet<ValId>,
-    ) -> impl Iterator<Item = (ValId, Subnet)> + 'a {
-        let epoch = slot.epoch(self.slots_per_epoch).as_u64();
-        let slot = slot.as_u64();
-        validators.iter().filter_map(move |val_id| {
-            // shake the val id using the epoch
-            let shaked_val_id = val_id.overflowing_add(epoch).0 % self.total_validators;
-            // assign to one of the committees
-            let subnet = Subnet(shaked_val_id % self.attestation_subnets);
-            // assign attesters using the slot
-            let is_attester = (shaked_val_id
-                + if self.att_subnets_is_relative {
-                    0
-                } else {
-                    shaked_val_id / self.attestation_subnets
+        position: u64,
+        committee_len: u64,
+    ) -> bool {
+        match self.aggregator_selection {
+            AggregatorSelection::Fixed => position < self.target_aggregators,
+            AggregatorSelection::Modulo => {
+                let selection_proof = spec::selection_proof(
+                    self.seed,
+                    spec::DOMAIN_SELECTION_PROOF,
+                    slot.as_u64(),
+                    *val_id,
+                );
+                spec::is_aggregator(committee_len, self.target_aggregators, &selection_proof)
+            }
+        }
+    }
+
+    /// Number of attestation committees in each slot.
+    pub fn committees_per_slot(&self) -> u64 {
+        self.committees_per_slot
+    }
+
+    /// Index and subnet of each attestation committee of the slot. Subnets without a committee
+    /// are idle during the slot.
+    pub fn get_committee_subnets(&self, slot: Slot) -> impl Iterator<Item = (u64, Subnet)> + '_ {
+        let slot_in_epoch = slot.as_u64() % self.slots_per_epoch;
+        (0..self.committees_per_slot)
+            .map(move |index| (index, self.committee_subnet(slot_in_epoch, index)))
+    }
+
+    pub fn slots_per_epoch(&self) -> u64 {
+        self.slots_per_epoch
+    }
+
+    pub fn attestation_subnets(&self) -> u64 {
+        self.attestation_subnets
+    }
+
+    pub fn sync_committee_subnets(&self) -> u64 {
+        self.sync_committee_subnets
+    }
+
+    /// Epochs at which forks activate.
+    pub fn fork_schedule(&self) -> &ForkSchedule {
+        &self.fork_schedule
+    }
+
+    /// Fork active in the slot.
+    pub fn fork_at(&self, slot: Slot) -> Fork {
+        self.fork_schedule
+            .fork_at_epoch(slot.epoch(self.slots_per_epoch))
+    }
+
+    /// Whether the sync committee position makes its validator aggregate the subnet in the slot.
+    /// Members holding several positions of the subnet aggregate it once, from their first one.
+    fn is_sync_committee_aggregator(&self, duty: &SyncDuty, slot: Slot) -> bool {
+        if !duty.first_in_subnet {
+            return false;
+        }
+        match self.aggregator_selection {
+            AggregatorSelection::Fixed => duty.position < self.target_sync_aggregators,
+            AggregatorSelection::Modulo => {
+                let selection_proof = spec::sync_selection_proof(
+                    self.seed,
+                    slot.as_u64(),
+                    *duty.subnet,
+                    *duty.val_id,
+                );
+                spec::is_sync_committee_aggregator(
+                    self.sync_subnet_size,
+                    self.target_sync_aggregators,
+                    &selection_proof,
+                )
+            }
+        }
+    }
+
+    /// Proposer of the slot, among all validators.
+    fn proposer(&self, slot: Slot) -> ValId {
+        match self.proposer_selection {
+            ProposerSelection::RoundRobin => ValId(slot.as_u64() % self.total_validators),
+            ProposerSelection::BalanceWeighted => {
+                let epoch = slot.epoch(self.slots_per_epoch).as_u64();
+                let seed = spec::proposer_seed(self.seed, epoch, slot.as_u64());
+                let index = spec::sample_by_balance(&seed, self.total_validators as usize, |v| {
+                    self.effective_balance(v)
                 })
-                % self.slots_per_epoch
-                == slot % self.slots_per_epoch;
-            is_attester.then_some((*val_id, subnet))
-        })
+                .next()
+                .expect("some validator has a positive effective balance");
+                ValId(index as u64)
+            }
+        }
+    }
+
+    /// Number of blobs of the slot's block, up to the limit of its fork. Drawn from a generator
+    /// seeded with the slot, so that it is the same every time the slot is looked at.
+    fn blob_count(&self, slot: Slot) -> u64 {
+        let preimage = [self.seed.to_le_bytes(), slot.as_u64().to_le_bytes()].concat();
+        self.blob_count
+            .sample(&mut seeded_rng(&preimage))
+            .min(self.fork_at(slot).max_blobs_per_block())
+    }
+
+    /// Operations of the given type sent by the validators over the epoch, by slot. Their number
+    /// follows a Poisson distribution, and each is sent by a random validator in a random slot.
+    /// Operations are distinct, since gossip would drop repeated ones: exits and BLS changes are
+    /// sent at most once per validator, and other operations at most once per validator and
+    /// slot. Drawn from a generator seeded with the epoch, so that they are the same every time
+    /// the epoch is looked at.
+    fn epoch_operations(
+        &self,
+        msg_type: MsgType,
+        epoch: Epoch,
+        validators: &BTreeSet<ValId>,
+    ) -> Vec<(Slot, ValId)> {
+        let rate = self.operation_rates.get(&msg_type).copied().unwrap_or(0.0);
+        if rate <= 0.0 || validators.is_empty() {
+            return Vec::new();
+        }
+        let preimage = [
+            self.seed.to_le_bytes().as_slice(),
+            &epoch.as_u64().to_le_bytes(),
+            msg_type.to_string().as_bytes(),
+        ]
+        .concat();
+        let mut rng = seeded_rng(&preimage);
+        let poisson = Poisson::new(rate).expect("rate is positive and finite");
+        let count = poisson.sample(&mut rng) as usize;
+        let start_slot = epoch.start_slot(self.slots_per_epoch);
+        let validators: Vec<_> = validators.iter().copied().collect();
+        let slots_per_epoch = self.slots_per_epoch as usize;
+        let once_per_validator = matches!(
+            msg_type,
+            MsgType::VoluntaryExit | MsgType::BlsToExecutionChange
+        );
+        let mut operations: Vec<_> = if once_per_validator {
+            index::sample(&mut rng, validators.len(), count.min(validators.len()))
+                .into_vec()
+                .into_iter()
+                .map(|validator| {
+                    let slot = start_slot + rng.gen_range(0..self.slots_per_epoch);
+                    (slot, validators[validator])
+                })
+                .collect()
+        } else {
+            let pairs = slots_per_epoch * validators.len();
+            index::sample(&mut rng, pairs, count.min(pairs))
+                .into_iter()
+                .map(|pair| {
+                    let slot = start_slot + (pair / validators.len()) as u64;
+                    (slot, validators[pair % validators.len()])
+                })
+                .collect()
+        };
+        operations.sort_by_key(|(slot, _)| *slot);
+        operations
     }
 
-    pub fn get_aggregates<'a>(
-        &'a self,
+    /// Duties of the validators over the epoch. Generating the messages of a slot from them only
+    /// visits the validators with a duty in it, instead of all of them.
+    pub fn epoch_duties(&self, epoch: Epoch, validators: &BTreeSet<ValId>) -> EpochDuties {
+        let start_slot = epoch.start_slot(self.slots_per_epoch);
+        let fork = self.fork_schedule.fork_at_epoch(epoch);
+
+        let proposers = epoch
+            .slot_iter(self.slots_per_epoch)
+            .map(|slot| Some(self.proposer(slot)).filter(|proposer| validators.contains(proposer)))
+            .collect();
+        let blob_counts = epoch
+            .slot_iter(self.slots_per_epoch)
+            .map(|slot| self.blob_count(slot))
+            .collect();
+
+        let mut attesters = vec![Vec::new(); self.slots_per_epoch as usize];
+        let mut aggregators = vec![Vec::new(); self.slots_per_epoch as usize];
+        let committees = self.epoch_committees(epoch.as_u64());
+        for val_id in validators {
+            let duty = committees.duty(*val_id);
+            let slot = start_slot + duty.slot_in_epoch;
+            let subnet = self.committee_subnet(duty.slot_in_epoch, duty.committee_index);
+            attesters[duty.slot_in_epoch as usize].push((*val_id, subnet));
+            if self.is_attestation_aggregator(
+                *val_id,
+                slot,
+                duty.position as u64,
+                duty.committee_len as u64,
+            ) {
+                aggregators[duty.slot_in_epoch as usize].push((*val_id, subnet));
+            }
+        }
+
+        // There is no sync committee before Altair.
+        let sync_duties = if MsgType::SyncCommitteeMessage.is_sent_in(fork) {
+            let sync_committee_period = epoch.as_u64() / crate::EPOCHS_PER_SYNC_COMMITTEE_PERIOD;
+            self.sync_duties(sync_committee_period, validators)
+        } else {
+            Vec::new()
+        };
+        let sync_aggregators = epoch
+            .slot_iter(self.slots_per_epoch)
+            .map(|slot| {
+                sync_duties
+                    .iter()
+                    .filter(|duty| self.is_sync_committee_aggregator(duty, slot))
+                    .map(|duty| (duty.val_id, duty.subnet))
+                    .collect()
+            })
+            .collect();
+        let sync_members = sync_duties
+            .into_iter()
+            .map(|duty| (duty.val_id, duty.subnet))
+            .collect();
+
+        let mut operations = vec![Vec::new(); self.slots_per_epoch as usize];
+        for msg_type in MsgType::iter().filter(MsgType::is_operation) {
+            for (slot, validator) in self.epoch_operations(msg_type, epoch, validators) {
+                operations[(slot - start_slot).as_u64() as usize].push((msg_type, validator));
+            }
+        }
+
+        EpochDuties {
+            epoch,
+            start_slot,
+            proposers,
+            fork,
+            blob_counts,
+            attesters,
+            aggregators,
+            sync_members,
+            sync_aggregators,
+            operations,
+            // Light client updates exist since Altair.
+            light_client_updates: self.serve_light_clients
+                && MsgType::LightClientFinalityUpdate.is_sent_in(fork),
+        }
+    }
+
+    /// Duties of the validators over the epoch, cached until a different epoch or set of
+    /// validators is requested.
+    fn cached_epoch_duties(&self, epoch: Epoch, validators: &BTreeSet<ValId>) -> Arc<EpochDuties> {
+        let mut cached = self.duties.lock().expect("lock is not poisoned");
+        if let Some((_, duties)) = cached.as_ref().filter(|(cached_validators, duties)| {
+            duties.epoch == epoch && cached_validators == validators
+        }) {
+            return duties.clone();
+        }
+
+        let duties = Arc::new(self.epoch_duties(epoch, validators));
+        *cached = Some((validators.clone(), duties.clone()));
+        duties
+    }
+
+    /// Messages of the given type sent by the validators in the slot, read from the duties of
+    /// its epoch.
+    pub fn get_messages(
+        &self,
+        msg_type: MsgType,
         slot: Slot,
-        validators: &'a HashSet<ValId>,
-    ) -> impl Iterator<Item = (ValId, Subnet)> + 'a {
-        let epoch = slot.epoch(self.slots_per_epoch).as_u64();
-        validators.iter().filter_map(move |val_id| {
-            // shake the val id using the epoch
-            let shaked_val_id = val_id.overflowing_add(epoch).0 % self.total_validators;
-            // assign to one of the committees
-            let subnet = Subnet(shaked_val_id % self.attestation_subnets);
-            // get an id inside the committee
-            let idx_in_commitee = shaked_val_id / self.attestation_subnets;
-            let is_aggregator = (idx_in_commitee / self.target_aggregators) == 0;
-            is_aggregator.then_some((*val_id, subnet))
-        })
+        validators: &BTreeSet<ValId>,
+    ) -> impl Iterator<Item = Message> {
+        let duties = self.cached_epoch_duties(slot.epoch(self.slots_per_epoch), validators);
+        duties
+            .messages(msg_type, slot)
+            .collect::<Vec<_>>()
+            .into_iter()
     }
 
-    pub fn get_sync_committee_messages<'a>(
-        &'a self,
+    /// Proposer of the slot, if among the validators.
+    pub fn get_blocks(&self, slot: Slot, validators: &BTreeSet<ValId>) -> Option<ValId> {
+        self.get_messages(MsgType::BeaconBlock, slot, validators)
+            .find_map(|message| match message {
+                Message::BeaconBlock { proposer, .. } => Some(proposer),
+                _ => None,
+            })
+    }
+
+    /// Blob sidecars sent along with the slot's block, as `(proposer, index, subnet)`.
+    pub fn get_blob_sidecars(
+        &self,
         slot: Slot,
-        validators: &'a HashSet<ValId>,
-    ) -> impl Iterator<Item = (ValId, Subnet)> + 'a {
-        let epoch = slot.epoch(self.slots_per_epoch).as_u64();
-        let sync_committee_period = epoch / crate::EPOCHS_PER_SYNC_COMMITTEE_PERIOD;
-        validators.iter().filter_map(move |val_id| {
-            // shake the val id using the sync_committee_period and move it back to
-            // the validator ids range.
-            let shaked_val_id =
-                val_id.overflowing_add(sync_committee_period).0 % self.total_validators;
-            let sync_committee_size = self.sync_subnet_size * self.sync_committee_subnets;
-            let in_commitee = shaked_val_id / sync_committee_size == 0;
-            in_commitee.then(|| {
-                let subnet = Subnet(shaked_val_id % self.sync_committee_subnets);
-                (*val_id, subnet)
+        validators: &BTreeSet<ValId>,
+    ) -> impl Iterator<Item = (ValId, u64, Subnet)> {
+        self.get_messages(MsgType::BlobSidecar, slot, validators)
+            .filter_map(|message| match message {
+                Message::BlobSidecar {
+                    proposer,
+                    index,
+                    subnet,
+                    ..
+                } => Some((proposer, index, subnet)),
+                _ => None,
             })
-        })
     }
 
-    pub fn get_sync_committee_aggregates<'a>(
-        &'a self,
+    /// Data column sidecars sent along with the slot's block, as `(proposer, column, subnet,
+    /// blob_count)`. The proposer publishes every column of blocks with blobs.
+    pub fn get_data_column_sidecars(
+        &self,
         slot: Slot,
-        validators: &'a HashSet<ValId>,
-    ) -> impl Iterator<Item = (ValId, Subnet)> + 'a {
-        let epoch = slot.epoch(self.slots_per_epoch).as_u64();
-        let sync_committee_period = epoch / crate::EPOCHS_PER_SYNC_COMMITTEE_PERIOD;
-        validators.iter().filter_map(move |val_id| {
-            // shake the val id using the sync_committee_period and move it back to
-            // the validator ids range.
-            let shaked_val_id =
-                val_id.overflowing_add(sync_committee_period).0 % self.total_validators;
-            let sync_committee_size = self.sync_subnet_size * self.sync_committee_subnets;
-            let in_commitee = shaked_val_id / sync_committee_size == 0;
-            let subnet = Subnet(shaked_val_id % self.sync_committee_subnets);
-            let id_in_subnet = shaked_val_id / self.sync_committee_subnets;
-            let is_aggregator = (id_in_subnet / self.target_aggregators) == 0;
-            (in_commitee && is_aggregator).then_some((*val_id, subnet))
-        })
+        validators: &BTreeSet<ValId>,
+    ) -> impl Iterator<Item = (ValId, u64, Subnet, u64)> {
+        self.get_messages(MsgType::DataColumnSidecar, slot, validators)
+            .filter_map(|message| match message {
+                Message::DataColumnSidecar {
+                    proposer,
+                    index,
+                    subnet,
+                    blob_count,
+                    ..
+                } => Some((proposer, index, subnet, blob_count)),
+                _ => None,
+            })
+    }
+
+    /// Validators sending an operation of the given type in the slot.
+    pub fn get_operations(
+        &self,
+        msg_type: MsgType,
+        slot: Slot,
+        validators: &BTreeSet<ValId>,
+    ) -> impl Iterator<Item = ValId> {
+        self.get_messages(msg_type, slot, validators)
+            .filter_map(|message| match message {
+                Message::VoluntaryExit { validator, .. }
+                | Message::ProposerSlashing { validator, .. }
+                | Message::AttesterSlashing { validator, .. }
+                | Message::BlsToExecutionChange { validator, .. } => Some(validator),
+                _ => None,
+            })
+    }
+
+    /// Whether the node sends light client updates in the slot.
+    pub fn get_light_client_updates(&self, slot: Slot) -> bool {
+        self.get_messages(MsgType::LightClientFinalityUpdate, slot, &BTreeSet::new())
+            .next()
+            .is_some()
+    }
+
+    pub fn get_attestations(
+        &self,
+        slot: Slot,
+        validators: &BTreeSet<ValId>,
+    ) -> impl Iterator<Item = (ValId, Subnet)> {
+        self.get_messages(MsgType::Attestation, slot, validators)
+            .filter_map(|message| match message {
+                Message::Attestation {
+                    attester, subnet, ..
+                } => Some((attester, subnet)),
+                _ => None,
+            })
+    }
+
+    pub fn get_aggregates(
+        &self,
+        slot: Slot,
+        validators: &BTreeSet<ValId>,
+    ) -> impl Iterator<Item = (ValId, Subnet)> {
+        self.get_messages(MsgType::AggregateAndProofAttestation, slot, validators)
+            .filter_map(|message| match message {
+                Message::AggregateAndProofAttestation {
+                    aggregator, subnet, ..
+                } => Some((aggregator, subnet)),
+                _ => None,
+            })
+    }
+
+    pub fn get_sync_committee_messages(
+        &self,
+        slot: Slot,
+        validators: &BTreeSet<ValId>,
+    ) -> impl Iterator<Item = (ValId, Subnet)> {
+        self.get_messages(MsgType::SyncCommitteeMessage, slot, validators)
+            .filter_map(|message| match message {
+                Message::SyncCommitteeMessage {
+                    validator, subnet, ..
+                } => Some((validator, subnet)),
+                _ => None,
+            })
+    }
+
+    pub fn get_sync_committee_aggregates(
+        &self,
+        slot: Slot,
+        validators: &BTreeSet<ValId>,
+    ) -> impl Iterator<Item = (ValId, Subnet)> {
+        self.get_messages(MsgType::SignedContributionAndProof, slot, validators)
+            .filter_map(|message| match message {
+                Message::SignedContributionAndProof {
+                    validator, subnet, ..
+                } => Some((validator, subnet)),
+                _ => None,
+            })
     }
 }
@@ -0,0 +1,244 @@
+//! Constants and helpers following the consensus and p2p specs: duty assignment in
+//! [`DutyMode::Spec`](crate::slot_generator::DutyMode::Spec), aggregator selection, subnets of
+//! each message type, data column custody, fork digests and the gossip message-id domain.
+
+use std::ops::Range;
+
+use eth2_hashing::hash;
+use swap_or_not_shuffle::compute_shuffled_index;
+
+pub const TARGET_COMMITTEE_SIZE: u64 = 128;
+pub const SHUFFLE_ROUND_COUNT: u8 = 90;
+/// 32 ETH, in Gwei.
+pub const MAX_EFFECTIVE_BALANCE: u64 = 32_000_000_000;
+/// 2048 ETH, in Gwei.
+pub const MAX_EFFECTIVE_BALANCE_ELECTRA: u64 = 2_048_000_000_000;
+const MAX_RANDOM_VALUE: u64 = u16::MAX as u64;
+pub const ATTESTATION_SUBNET_COUNT: u64 = 64;
+pub const SYNC_COMMITTEE_SUBNET_COUNT: u64 = 4;
+pub const MAX_BLOBS_PER_BLOCK: u64 = 6;
+pub const MAX_BLOBS_PER_BLOCK_ELECTRA: u64 = 9;
+pub const BLOB_SIDECAR_SUBNET_COUNT: u64 = 6;
+pub const BLOB_SIDECAR_SUBNET_COUNT_ELECTRA: u64 = 9;
+pub const NUMBER_OF_COLUMNS: u64 = 128;
+pub const NUMBER_OF_CUSTODY_GROUPS: u64 = 128;
+pub const DATA_COLUMN_SIDECAR_SUBNET_COUNT: u64 = 128;
+pub const CUSTODY_REQUIREMENT: u64 = 4;
+pub const VALIDATOR_CUSTODY_REQUIREMENT: u64 = 8;
+/// 32 ETH, in Gwei.
+pub const BALANCE_PER_ADDITIONAL_CUSTODY_GROUP: u64 = 32_000_000_000;
+
+pub const DOMAIN_BEACON_PROPOSER: [u8; 4] = [0, 0, 0, 0];
+pub const DOMAIN_BEACON_ATTESTER: [u8; 4] = [1, 0, 0, 0];
+pub const DOMAIN_SELECTION_PROOF: [u8; 4] = [5, 0, 0, 0];
+pub const DOMAIN_SYNC_COMMITTEE: [u8; 4] = [7, 0, 0, 0];
+pub const DOMAIN_SYNC_COMMITTEE_SELECTION_PROOF: [u8; 4] = [8, 0, 0, 0];
+
+pub const MESSAGE_DOMAIN_VALID_SNAPPY: [u8; 4] = [1, 0, 0, 0];
+
+/// RANDAO-like mix of an epoch. Derived from the seed instead of the proposers' reveals.
+pub fn randao_mix(seed: u64, epoch: u64) -> Vec<u8> {
+    hash(&[seed.to_le_bytes(), epoch.to_le_bytes()].concat())
+}
+
+/// Seed of an epoch for a domain, as in `get_seed`.
+pub fn get_seed(seed: u64, epoch: u64, domain_type: [u8; 4]) -> Vec<u8> {
+    let mut preimage = domain_type.to_vec();
+    preimage.extend(epoch.to_le_bytes());
+    preimage.extend(randao_mix(seed, epoch));
+    hash(&preimage)
+}
+
+/// Seed of a slot's proposer selection, as in `get_beacon_proposer_index`.
+pub fn proposer_seed(seed: u64, epoch: u64, slot: u64) -> Vec<u8> {
+    let mut preimage = get_seed(seed, epoch, DOMAIN_BEACON_PROPOSER);
+    preimage.extend(slot.to_le_bytes());
+    hash(&preimage)
+}
+
+/// Validators sampled with a chance weighted by their effective balance, as done by
+/// `compute_proposer_index` and `get_next_sync_committee_indices` since Electra. The same
+/// validator can be sampled more than once.
+pub fn sample_by_balance<'a>(
+    seed: &'a [u8],
+    total_validators: usize,
+    effective_balance: impl Fn(usize) -> u64 + 'a,
+) -> impl Iterator<Item = usize> + 'a {
+    let mut random_bytes = Vec::new();
+    (0..).filter_map(move |i: usize| {
+        let candidate = compute_shuffled_index(
+            i % total_validators,
+            total_validators,
+            seed,
+            SHUFFLE_ROUND_COUNT,
+        )
+        .expect("validator count is within the shuffle limits");
+        if i.is_multiple_of(16) {
+            let mut preimage = seed.to_vec();
+            preimage.extend((i as u64 / 16).to_le_bytes());
+            random_bytes = hash(&preimage);
+        }
+        let offset = i % 16 * 2;
+        let random_value = u64::from(u16::from_le_bytes([
+            random_bytes[offset],
+            random_bytes[offset + 1],
+        ]));
+        (effective_balance(candidate) * MAX_RANDOM_VALUE
+            >= MAX_EFFECTIVE_BALANCE_ELECTRA * random_value)
+            .then_some(candidate)
+    })
+}
+
+/// Number of committees in each slot, as in `get_committee_count_per_slot`. There is always at
+/// least one, so `max_committees_per_slot` must be positive.
+pub fn committee_count_per_slot(
+    active_validators: u64,
+    slots_per_epoch: u64,
+    max_committees_per_slot: u64,
+) -> u64 {
+    (active_validators / slots_per_epoch / TARGET_COMMITTEE_SIZE).clamp(1, max_committees_per_slot)
+}
+
+/// Range of the shuffled validators that make up a committee, as in `compute_committee`.
+pub fn committee_range(index_count: usize, index: u64, count: u64) -> Range<usize> {
+    let bound = |index: u64| (index_count as u64 * index / count) as usize;
+    bound(index)..bound(index + 1)
+}
+
+/// Subnet in which a committee publishes its attestations, as in
+/// `compute_subnet_for_attestation`.
+pub fn compute_subnet_for_attestation(
+    committees_per_slot: u64,
+    slot_in_epoch: u64,
+    committee_index: u64,
+    attestation_subnets: u64,
+) -> u64 {
+    let committees_since_epoch_start = committees_per_slot * slot_in_epoch;
+    (committees_since_epoch_start + committee_index) % attestation_subnets
+}
+
+/// Subnet on which a blob sidecar is published, as in `compute_subnet_for_blob_sidecar`. The
+/// number of subnets depends on the fork.
+pub fn compute_subnet_for_blob_sidecar(blob_index: u64, blob_sidecar_subnet_count: u64) -> u64 {
+    blob_index % blob_sidecar_subnet_count
+}
+
+/// Subnet on which a data column sidecar is published, as in
+/// `compute_subnet_for_data_column_sidecar`.
+pub fn compute_subnet_for_data_column_sidecar(column_index: u64) -> u64 {
+    column_index % DATA_COLUMN_SIDECAR_SUBNET_COUNT
+}
+
+/// Custody groups of a node, as in `get_custody_groups`. The node id is a little endian
+/// 256-bit integer.
+pub fn get_custody_groups(node_id: [u8; 32], custody_group_count: u64) -> Vec<u64> {
+    assert!(custody_group_count <= NUMBER_OF_CUSTODY_GROUPS);
+    if custody_group_count == NUMBER_OF_CUSTODY_GROUPS {
+        return (0..NUMBER_OF_CUSTODY_GROUPS).collect();
+    }
+    let mut current_id = node_id;
+    let mut custody_groups = Vec::new();
+    while (custody_groups.len() as u64) < custody_group_count {
+        let custody_group = bytes_to_u64(&hash(&current_id)) % NUMBER_OF_CUSTODY_GROUPS;
+        if !custody_groups.contains(&custody_group) {
+            custody_groups.push(custody_group);
+        }
+        // Increment, wrapping around to zero after the largest id.
+        for byte in current_id.iter_mut() {
+            let (incremented, overflow) = byte.overflowing_add(1);
+            *byte = incremented;
+            if !overflow {
+                break;
+            }
+        }
+    }
+    custody_groups.sort_unstable();
+    custody_groups
+}
+
+/// Columns of a custody group, as in `compute_columns_for_custody_group`.
+pub fn compute_columns_for_custody_group(custody_group: u64) -> impl Iterator<Item = u64> {
+    let columns_per_group = NUMBER_OF_COLUMNS / NUMBER_OF_CUSTODY_GROUPS;
+    (0..columns_per_group).map(move |i| NUMBER_OF_CUSTODY_GROUPS * i + custody_group)
+}
+
+/// Custody group count of a node holding validators with the given total effective balance, as
+/// in `get_validators_custody_requirement`. Nodes without validators custody the minimum.
+pub fn custody_group_count(validators_balance: Option<u64>) -> u64 {
+    match validators_balance {
+        None => CUSTODY_REQUIREMENT,
+        Some(balance) => (balance / BALANCE_PER_ADDITIONAL_CUSTODY_GROUP)
+            .clamp(VALIDATOR_CUSTODY_REQUIREMENT, NUMBER_OF_CUSTODY_GROUPS),
+    }
+}
+
+/// Root of the fork data of a fork version, as in `compute_fork_data_root`.
+pub fn compute_fork_data_root(
+    current_version: [u8; 4],
+    genesis_validators_root: [u8; 32],
+) -> Vec<u8> {
+    let mut preimage = current_version.to_vec();
+    preimage.resize(32, 0);
+    preimage.extend(genesis_validators_root);
+    hash(&preimage)
+}
+
+/// Digest of a fork version, as in `compute_fork_digest` until Electra.
+pub fn compute_fork_digest(current_version: [u8; 4], genesis_validators_root: [u8; 32]) -> [u8; 4] {
+    let root = compute_fork_data_root(current_version, genesis_validators_root);
+    root[..4].try_into().expect("roots are 32 bytes long")
+}
+
+/// Fork digest with the blob parameters of its epoch mixed in, as done by `compute_fork_digest`
+/// since Fulu.
+pub fn mix_blob_parameters(fork_digest: [u8; 4], epoch: u64, max_blobs_per_block: u64) -> [u8; 4] {
+    let mut preimage = epoch.to_le_bytes().to_vec();
+    preimage.extend(max_blobs_per_block.to_le_bytes());
+    let blob_parameters_hash = hash(&preimage);
+    let mut digest = fork_digest;
+    for (byte, mask) in digest.iter_mut().zip(blob_parameters_hash) {
+        *byte ^= mask;
+    }
+    digest
+}
+
+/// Stand-in for a validator's selection proof. Takes the place of its signature over the slot,
+/// so it is unique to the validator and slot and looks random.
+pub fn selection_proof(seed: u64, domain_type: [u8; 4], slot: u64, val_id: u64) -> Vec<u8> {
+    let mut preimage = domain_type.to_vec();
+    preimage.extend(seed.to_le_bytes());
+    preimage.extend(slot.to_le_bytes());
+    preimage.extend(val_id.to_le_bytes());
+    hash(&preimage)
+}
+
+/// Stand-in for a sync committee member's selection proof, which signs both the slot and the
+/// subcommittee.
+pub fn sync_selection_proof(seed: u64, slot: u64, subcommittee_index: u64, val_id: u64) -> Vec<u8> {
+    let mut preimage = selection_proof(seed, DOMAIN_SYNC_COMMITTEE_SELECTION_PROOF, slot, val_id);
+    preimage.extend(subcommittee_index.to_le_bytes());
+    hash(&preimage)
+}
+
+/// Whether a selection proof makes its validator an aggregator of the committee, as in
+/// `is_aggregator`.
+pub fn is_aggregator(committee_len: u64, target_aggregators: u64, selection_proof: &[u8]) -> bool {
+    let modulo = (committee_len / target_aggregators).max(1);
+    bytes_to_u64(&hash(selection_proof)).is_multiple_of(modulo)
+}
+
+/// Whether a selection proof makes its validator an aggregator of the sync subnet, as in
+/// `is_sync_committee_aggregator`.
+pub fn is_sync_committee_aggregator(
+    sync_subnet_size: u64,
+    target_aggregators: u64,
+    selection_proof: &[u8],
+) -> bool {
+    let modulo = (sync_subnet_size / target_aggregators).max(1);
+    bytes_to_u64(&hash(selection_proof)).is_multiple_of(modulo)
+}
+
+/// Little endian integer of the first 8 bytes, as in `bytes_to_uint64`.
+fn bytes_to_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().expect("hashes are 32 bytes long"))
+}
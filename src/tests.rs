@@ -1,8 +1,38 @@
-use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    time::{Duration, Instant},
+};
 
-use crate::slot_generator::{SlotGenerator, Subnet, ValId};
+use crate::{
+    builder::GeneratorBuilder,
+    fork::{Fork, ForkContext, ForkSchedule},
+    jitter::Jitter,
+    sizes::{self, PayloadMode},
+    slot_generator::{
+        AggregatorSelection, BlobCount, DutyMode, ProposerSelection, SlotGenerator, Subnet,
+        SyncCommitteeSelection, ValId,
+    },
+    spec,
+    topic::GossipTopic,
+    EmittedMessage, Generator, Message, MsgType, SlotPhase, TimedMessage,
+};
 
-use slot_clock::Slot;
+use futures::{FutureExt, StreamExt};
+use slot_clock::{ManualSlotClock, Slot, SlotClock};
+use strum::IntoEnumIterator;
+
+/// Returns every message the generator has ready without waiting.
+fn drain_ready(generator: &mut Generator<ManualSlotClock>) -> Vec<Message> {
+    drain_ready_emitted(generator)
+        .into_iter()
+        .map(|emitted| emitted.message)
+        .collect()
+}
+
+/// Returns every message the generator has ready without waiting, with its timing.
+fn drain_ready_emitted(generator: &mut Generator<ManualSlotClock>) -> Vec<EmittedMessage> {
+    std::iter::from_fn(|| generator.next().now_or_never().flatten()).collect()
+}
 
 #[test]
 fn test_attestations() {
@@ -175,3 +205,1905 @@ fn test_sync_aggregates() {
         .values()
         .all(|vals| vals.len() == aggregators as usize));
 }
+
+#[tokio::test]
+async fn test_manual_clock() {
+    let total_validators = 4096;
+    let slot_duration = Duration::from_secs(12);
+    let slot_clock = ManualSlotClock::new(Slot::new(0), Duration::ZERO, slot_duration);
+
+    let mut generator = GeneratorBuilder::default()
+        .slot_clock_impl(slot_clock.clone())
+        .total_validators(total_validators)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+
+    // Nothing is sent until the clock moves forward.
+    assert!(drain_ready(&mut generator).is_empty());
+
+    // Only the block is sent at the start of the slot.
+    slot_clock.set_slot(1);
+    let slot_start_msgs = drain_ready(&mut generator);
+    assert_eq!(slot_start_msgs.len(), 1);
+    assert!(matches!(
+        slot_start_msgs[0],
+        Message::BeaconBlock { proposer: ValId(1), slot } if slot == 1
+    ));
+
+    // Attestations and sync committee messages are sent at 1/3 of the slot.
+    slot_clock.advance_time(slot_duration / 3);
+    let attestation_msgs = drain_ready(&mut generator);
+    assert!(!attestation_msgs.is_empty());
+    assert!(attestation_msgs.iter().all(|msg| matches!(
+        msg,
+        Message::Attestation { .. } | Message::SyncCommitteeMessage { .. }
+    )));
+
+    // Aggregates are sent at 2/3 of the slot.
+    slot_clock.advance_time(slot_duration / 3);
+    let aggregate_msgs = drain_ready(&mut generator);
+    assert!(!aggregate_msgs.is_empty());
+    assert!(aggregate_msgs.iter().all(|msg| matches!(
+        msg,
+        Message::AggregateAndProofAttestation { .. } | Message::SignedContributionAndProof { .. }
+    )));
+
+    // The next slot is emitted as soon as the clock gets there.
+    slot_clock.set_slot(2);
+    assert!(drain_ready(&mut generator)
+        .iter()
+        .any(|msg| matches!(msg, Message::BeaconBlock { proposer: ValId(2), slot } if *slot == 2)));
+}
+
+#[tokio::test]
+async fn test_manual_clock_wakes_waiting_stream() {
+    let total_validators = 4096;
+    let slot_clock = ManualSlotClock::new(Slot::new(0), Duration::ZERO, Duration::from_secs(12));
+
+    let mut generator = GeneratorBuilder::default()
+        .slot_clock_impl(slot_clock.clone())
+        .total_validators(total_validators)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+
+    // The stream is already waiting for the next slot when another task moves the clock forward.
+    let advance_clock = tokio::spawn({
+        let slot_clock = slot_clock.clone();
+        async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            slot_clock.set_slot(1);
+        }
+    });
+    let start = Instant::now();
+    let emitted = tokio::time::timeout(Duration::from_secs(1), generator.next())
+        .await
+        .expect("the stream wakes up soon after the clock moves");
+    assert!(start.elapsed() < Duration::from_millis(500));
+    advance_clock.await.unwrap();
+    assert!(matches!(
+        emitted.unwrap().message,
+        Message::BeaconBlock { slot, .. } if slot == 1
+    ));
+}
+
+#[tokio::test]
+async fn test_time_scale() {
+    let total_validators = 4096;
+    let genesis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap();
+
+    // Twelve second slots pass in 10ms.
+    let mut generator = Generator::builder()
+        .slot_clock(0, genesis, Duration::from_secs(12))
+        .time_scale(1200)
+        .total_validators(total_validators)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+    assert_eq!(generator.slot_duration(), Duration::from_secs(12));
+
+    let mut block_slots = Vec::new();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while block_slots.len() < 5 {
+            if let Some(Message::BeaconBlock { slot, .. }) =
+                generator.next().await.map(|emitted| emitted.message)
+            {
+                block_slots.push(slot.as_u64());
+            }
+        }
+    })
+    .await
+    .expect("five slots pass well within the timeout");
+
+    let first = block_slots[0];
+    assert_eq!(block_slots, (first..first + 5).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn test_time_scale_from_default_genesis() {
+    let total_validators = 4096;
+
+    // The default clock starts at the UNIX epoch, so compressed time since genesis is centuries
+    // ahead of real time.
+    let mut generator = Generator::builder()
+        .time_scale(1200)
+        .total_validators(total_validators)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+    let emitted = tokio::time::timeout(Duration::from_secs(5), generator.next())
+        .await
+        .expect("a slot passes well within the timeout")
+        .unwrap();
+    assert!(emitted.scheduled.as_nanos() > u128::from(u64::MAX));
+
+    // Simulated times that don't fit are rejected.
+    assert!(Generator::builder()
+        .slot_clock(
+            0,
+            Duration::from_secs(u64::MAX / 2),
+            Duration::from_secs(12)
+        )
+        .time_scale(4)
+        .total_validators(total_validators)
+        .build(HashSet::new())
+        .is_err());
+}
+
+#[test]
+fn test_messages_for_slots() {
+    let total_validators = 4096;
+    let slots_per_epoch = 32;
+    let generator = Generator::builder()
+        .total_validators(total_validators)
+        .slots_per_epoch(slots_per_epoch)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+
+    let slot_duration = generator.slot_duration();
+    let mut blocks = 0;
+    let mut attesters = HashSet::new();
+    let mut last_slot_and_offset = (Slot::new(0), Duration::ZERO);
+    for TimedMessage {
+        message,
+        phase,
+        offset,
+    } in generator.messages_for_slots(Slot::new(0)..Slot::new(slots_per_epoch))
+    {
+        let slot = match &message {
+            Message::BeaconBlock { slot, .. } => {
+                blocks += 1;
+                assert_eq!(phase, SlotPhase::Block);
+                *slot
+            }
+            Message::BlobSidecar { slot, .. }
+            | Message::DataColumnSidecar { slot, .. }
+            | Message::VoluntaryExit { slot, .. }
+            | Message::ProposerSlashing { slot, .. }
+            | Message::AttesterSlashing { slot, .. }
+            | Message::BlsToExecutionChange { slot, .. } => {
+                assert_eq!(phase, SlotPhase::Block);
+                *slot
+            }
+            Message::Attestation { attester, slot, .. } => {
+                assert!(attesters.insert(*attester), "one attestation per epoch");
+                assert_eq!(phase, SlotPhase::Attestation);
+                *slot
+            }
+            Message::SyncCommitteeMessage { slot, .. } => {
+                assert_eq!(phase, SlotPhase::Attestation);
+                *slot
+            }
+            Message::AggregateAndProofAttestation { slot, .. }
+            | Message::SignedContributionAndProof { slot, .. }
+            | Message::LightClientFinalityUpdate { slot, .. }
+            | Message::LightClientOptimisticUpdate { slot, .. } => {
+                assert_eq!(phase, SlotPhase::Aggregate);
+                *slot
+            }
+        };
+        assert_eq!(offset, phase.default_offset(slot_duration));
+        // Messages come out in the order they are sent.
+        assert!(last_slot_and_offset <= (slot, offset));
+        last_slot_and_offset = (slot, offset);
+    }
+
+    assert_eq!(blocks, slots_per_epoch);
+    assert_eq!(attesters.len(), total_validators as usize);
+}
+
+#[tokio::test]
+async fn test_custom_phase_offsets() {
+    let total_validators = 4096;
+    let slot_clock = ManualSlotClock::new(Slot::new(0), Duration::ZERO, Duration::from_secs(12));
+
+    let mut generator = GeneratorBuilder::default()
+        .slot_clock_impl(slot_clock.clone())
+        .attestation_offset(Duration::from_secs(2))
+        .aggregate_offset(Duration::from_secs(6))
+        .total_validators(total_validators)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+
+    slot_clock.set_slot(1);
+    assert_eq!(drain_ready(&mut generator).len(), 1);
+
+    slot_clock.advance_time(Duration::from_secs(2));
+    assert!(drain_ready(&mut generator).iter().all(|msg| matches!(
+        msg,
+        Message::Attestation { .. } | Message::SyncCommitteeMessage { .. }
+    )));
+
+    slot_clock.advance_time(Duration::from_millis(3999));
+    assert!(drain_ready(&mut generator).is_empty());
+    slot_clock.advance_time(Duration::from_millis(1));
+    assert!(!drain_ready(&mut generator).is_empty());
+
+    assert!(GeneratorBuilder::<ManualSlotClock>::default()
+        .attestation_offset(Duration::from_secs(8))
+        .aggregate_offset(Duration::from_secs(6))
+        .total_validators(total_validators)
+        .build(HashSet::new())
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_each_message_once_per_slot() {
+    let total_validators = 4096;
+    let slot_duration = Duration::from_secs(12);
+    let slot_clock = ManualSlotClock::new(Slot::new(0), Duration::ZERO, slot_duration);
+
+    let mut generator = GeneratorBuilder::default()
+        .slot_clock_impl(slot_clock.clone())
+        .total_validators(total_validators)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+
+    for slot in 1..=3 {
+        // Run the whole slot, stopping right before the next one starts.
+        slot_clock.set_slot(slot);
+        let mut msgs = drain_ready(&mut generator);
+        for _ in 0..12 {
+            slot_clock.advance_time(slot_duration / 12 - Duration::from_millis(1));
+            msgs.extend(drain_ready(&mut generator));
+        }
+
+        let unique_msgs: HashSet<_> = msgs.iter().cloned().collect();
+        assert_eq!(unique_msgs.len(), msgs.len(), "no message is sent twice");
+
+        let mut counts = BTreeMap::<MsgType, usize>::new();
+        for msg in &msgs {
+            *counts.entry(msg.msg_type()).or_default() += 1;
+        }
+        for msg_type in MsgType::iter() {
+            let expected = generator
+                .slot_generator
+                .get_messages(msg_type, Slot::new(slot), &generator.validators)
+                .count();
+            assert_eq!(
+                counts.get(&msg_type).copied().unwrap_or_default(),
+                expected,
+                "unexpected number of {msg_type} messages"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_each_message_once_for_slots() {
+    let total_validators = 4096;
+    let generator = Generator::builder()
+        .total_validators(total_validators)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+
+    let slot = Slot::new(5);
+    let mut counts = BTreeMap::<MsgType, usize>::new();
+    for timed in generator.messages_for_slots(slot..slot + 1) {
+        *counts.entry(timed.message.msg_type()).or_default() += 1;
+    }
+    for msg_type in MsgType::iter() {
+        let expected = generator
+            .slot_generator
+            .get_messages(msg_type, slot, &generator.validators)
+            .count();
+        assert_eq!(counts.get(&msg_type).copied().unwrap_or_default(), expected);
+    }
+}
+
+#[tokio::test]
+async fn test_emitted_message_timing() {
+    let total_validators = 4096;
+    let slots_per_epoch = 32;
+    let genesis = Duration::from_secs(1_000);
+    let slot_duration = Duration::from_secs(12);
+    let slot_clock = ManualSlotClock::new(Slot::new(0), genesis, slot_duration);
+
+    let mut generator = GeneratorBuilder::default()
+        .slot_clock_impl(slot_clock.clone())
+        .slots_per_epoch(slots_per_epoch)
+        .total_validators(total_validators)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+
+    let slot = Slot::new(slots_per_epoch + 1);
+    let slot_start = genesis + slot_duration * (slots_per_epoch as u32 + 1);
+    slot_clock.set_slot(slot.as_u64());
+    let before = Instant::now();
+    let block = drain_ready_emitted(&mut generator).pop().unwrap();
+    assert_eq!(block.slot, slot);
+    assert_eq!(block.epoch.as_u64(), 1);
+    assert_eq!(block.phase, SlotPhase::Block);
+    assert_eq!(block.scheduled, slot_start);
+    assert_eq!(block.delay, Duration::ZERO);
+    assert!(block.emitted >= before);
+
+    // Attestations come out a second late.
+    slot_clock.advance_time(slot_duration / 3 + Duration::from_secs(1));
+    for emitted in drain_ready_emitted(&mut generator) {
+        assert_eq!(emitted.phase, SlotPhase::Attestation);
+        assert_eq!(emitted.scheduled, slot_start + slot_duration / 3);
+        assert_eq!(emitted.delay, Duration::from_secs(1));
+    }
+}
+
+#[tokio::test]
+async fn test_jitter_spreads_messages() {
+    let total_validators = 4096;
+    let slot_duration = Duration::from_secs(12);
+    let slot_clock = ManualSlotClock::new(Slot::new(0), Duration::ZERO, slot_duration);
+    let window = Duration::from_secs(2);
+
+    let mut generator = GeneratorBuilder::default()
+        .slot_clock_impl(slot_clock.clone())
+        .jitter(MsgType::Attestation, Jitter::Uniform { max: window })
+        .seed(7)
+        .total_validators(total_validators)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+    let expected_attestations = generator
+        .slot_generator
+        .get_messages(MsgType::Attestation, Slot::new(1), &generator.validators)
+        .count();
+
+    slot_clock.set_slot(1);
+    slot_clock.advance_time(slot_duration / 3);
+    let mut attestations = Vec::new();
+    let mut released_per_step = Vec::new();
+    for _ in 0..4 {
+        let released: Vec<_> = drain_ready_emitted(&mut generator)
+            .into_iter()
+            .filter(|emitted| emitted.message.msg_type() == MsgType::Attestation)
+            .collect();
+        released_per_step.push(released.len());
+        attestations.extend(released);
+        slot_clock.advance_time(window / 2);
+    }
+
+    // Attestations are released over the window, in the order they are due.
+    assert_eq!(attestations.len(), expected_attestations);
+    assert!(released_per_step[1] > 0 && released_per_step[2] > 0);
+    assert!(attestations
+        .windows(2)
+        .all(|pair| pair[0].scheduled <= pair[1].scheduled));
+    let phase_start = slot_duration + slot_duration / 3;
+    assert!(attestations.iter().all(|emitted| {
+        emitted.scheduled >= phase_start && emitted.scheduled < phase_start + window
+    }));
+}
+
+#[test]
+fn test_jitter_distributions() {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let mean = Duration::from_millis(500);
+    for jitter in [
+        Jitter::Uniform { max: mean * 2 },
+        Jitter::Normal {
+            mean,
+            std_dev: mean / 5,
+        },
+        Jitter::Exponential { mean },
+    ] {
+        let samples: Vec<_> = (0..10_000).map(|_| jitter.sample(&mut rng)).collect();
+        let avg = samples.iter().sum::<Duration>() / samples.len() as u32;
+        assert!(
+            avg.abs_diff(mean) < mean / 10,
+            "{jitter:?} averaged {avg:?}"
+        );
+    }
+
+    // Zero sized windows send right away.
+    assert_eq!(
+        Jitter::Exponential {
+            mean: Duration::ZERO
+        }
+        .sample(&mut rng),
+        Duration::ZERO
+    );
+}
+
+#[tokio::test]
+async fn test_seeded_runs_are_reproducible() {
+    let total_validators = 4096;
+    let slot_duration = Duration::from_secs(12);
+
+    let run = |seed: u64| {
+        let slot_clock = ManualSlotClock::new(Slot::new(0), Duration::ZERO, slot_duration);
+        let mut generator = GeneratorBuilder::default()
+            .slot_clock_impl(slot_clock.clone())
+            .jitter(
+                MsgType::Attestation,
+                Jitter::Exponential {
+                    mean: Duration::from_millis(300),
+                },
+            )
+            .seed(seed)
+            .total_validators(total_validators)
+            .build((0..total_validators).map(ValId).collect())
+            .unwrap();
+
+        slot_clock.set_slot(1);
+        slot_clock.advance_time(slot_duration - Duration::from_millis(1));
+        drain_ready_emitted(&mut generator)
+            .into_iter()
+            .map(|emitted| {
+                let payload = generator.payload(&emitted.message);
+                (emitted.message, emitted.scheduled, payload)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let first_run = run(42);
+    assert!(!first_run.is_empty());
+    assert!(first_run == run(42), "same seed, same run");
+
+    let other_run = run(43);
+    assert!(
+        first_run
+            .iter()
+            .zip(other_run.iter())
+            .any(|(first, other)| first.2 != other.2),
+        "payloads depend on the seed"
+    );
+}
+
+#[test]
+fn test_spec_committees() {
+    let slots_per_epoch = 32;
+    let total_validators = 16384;
+    let attestation_subnets = 64;
+    let aggregators = 16;
+
+    let spec_generator = |seed| {
+        SlotGenerator::new(
+            slots_per_epoch,
+            attestation_subnets,
+            1,
+            1,
+            aggregators,
+            total_validators,
+        )
+        .with_duty_mode(DutyMode::Spec)
+        .with_seed(seed)
+    };
+    let slot_generator = spec_generator(1);
+    let all_validators = (0..total_validators).map(ValId).collect();
+
+    // 16384 validators make 4 committees of 128 per slot.
+    let committees_per_slot = 4;
+    let mut epoch_attesters = HashSet::new();
+    for slot in Slot::new(0)
+        .epoch(slots_per_epoch)
+        .slot_iter(slots_per_epoch)
+    {
+        let mut subnet_attesters = BTreeMap::<Subnet, BTreeSet<ValId>>::default();
+        for (val_id, subnet) in slot_generator.get_attestations(slot, &all_validators) {
+            assert!(
+                epoch_attesters.insert(val_id),
+                "each validator attests once per epoch"
+            );
+            subnet_attesters.entry(subnet).or_default().insert(val_id);
+        }
+
+        // Committees go to consecutive subnets as in `compute_subnet_for_attestation`.
+        let slot_in_epoch = slot.as_u64() % slots_per_epoch;
+        let expected_subnets: BTreeSet<_> = (0..committees_per_slot)
+            .map(|index| {
+                Subnet((committees_per_slot * slot_in_epoch + index) % attestation_subnets)
+            })
+            .collect();
+        assert_eq!(
+            subnet_attesters.keys().copied().collect::<BTreeSet<_>>(),
+            expected_subnets
+        );
+        assert!(subnet_attesters
+            .values()
+            .all(|attesters| attesters.len() == 128));
+
+        // The first members of each committee aggregate.
+        let mut subnet_aggregators = BTreeMap::<Subnet, BTreeSet<ValId>>::default();
+        for (val_id, subnet) in slot_generator.get_aggregates(slot, &all_validators) {
+            assert!(subnet_attesters[&subnet].contains(&val_id));
+            subnet_aggregators.entry(subnet).or_default().insert(val_id);
+        }
+        assert_eq!(subnet_aggregators.len(), committees_per_slot as usize);
+        assert!(subnet_aggregators
+            .values()
+            .all(|vals| vals.len() == aggregators as usize));
+    }
+    assert_eq!(epoch_attesters.len(), total_validators as usize);
+
+    // Committees are shuffled, not assigned by index, and depend on the seed and epoch.
+    let first_slot_attesters = |slot_generator: &SlotGenerator, slot| {
+        slot_generator
+            .get_attestations(Slot::new(slot), &all_validators)
+            .map(|(val_id, _)| val_id)
+            .collect::<BTreeSet<_>>()
+    };
+    let attesters = first_slot_attesters(&slot_generator, 0);
+    assert_ne!(attesters, (0..512).map(ValId).collect());
+    assert_ne!(attesters, first_slot_attesters(&spec_generator(2), 0));
+    assert_ne!(
+        attesters,
+        first_slot_attesters(&slot_generator, slots_per_epoch)
+    );
+}
+
+#[test]
+fn test_committees_per_slot() {
+    let slots_per_epoch = 32;
+    let attestation_subnets = 64;
+
+    for (total_validators, committees_per_slot) in [(2048, 1), (16384, 4), (1_000_000, 64)] {
+        let slot_generator = SlotGenerator::new(
+            slots_per_epoch,
+            attestation_subnets,
+            1,
+            1,
+            16,
+            total_validators,
+        );
+        assert_eq!(slot_generator.committees_per_slot(), committees_per_slot);
+    }
+
+    // With few validators, a single committee attests in each slot and most subnets are idle.
+    let total_validators = 2048;
+    let slot_generator = SlotGenerator::new(
+        slots_per_epoch,
+        attestation_subnets,
+        1,
+        1,
+        16,
+        total_validators,
+    );
+    let all_validators = (0..total_validators).map(ValId).collect();
+    let mut epoch_subnets = BTreeSet::new();
+    for slot in Slot::new(0)
+        .epoch(slots_per_epoch)
+        .slot_iter(slots_per_epoch)
+    {
+        let subnets: BTreeSet<_> = slot_generator
+            .get_attestations(slot, &all_validators)
+            .map(|(_, subnet)| subnet)
+            .collect();
+        let committee_subnets: BTreeSet<_> = slot_generator
+            .get_committee_subnets(slot)
+            .map(|(_, subnet)| subnet)
+            .collect();
+        assert_eq!(subnets, committee_subnets);
+        assert_eq!(subnets.len(), 1);
+        epoch_subnets.extend(subnets);
+    }
+    assert_eq!(epoch_subnets.len(), slots_per_epoch as usize);
+}
+
+#[test]
+fn test_modulo_aggregator_selection() {
+    let slots_per_epoch = 32;
+    let total_validators = 16384;
+    let attestation_subnets = 64;
+
+    let slot_generator = |aggregators, seed| {
+        SlotGenerator::new(
+            slots_per_epoch,
+            attestation_subnets,
+            1,
+            1,
+            aggregators,
+            total_validators,
+        )
+        .with_duty_mode(DutyMode::Spec)
+        .with_aggregator_selection(AggregatorSelection::Modulo)
+        .with_seed(seed)
+    };
+    let all_validators = (0..total_validators).map(ValId).collect();
+    let epoch_slots = || {
+        Slot::new(0)
+            .epoch(slots_per_epoch)
+            .slot_iter(slots_per_epoch)
+    };
+    let aggregators_per_committee = |slot_generator: &SlotGenerator| {
+        let mut counts = Vec::new();
+        for slot in epoch_slots() {
+            let mut subnet_aggregators = BTreeMap::<Subnet, usize>::new();
+            for (_, subnet) in slot_generator.get_committee_subnets(slot) {
+                subnet_aggregators.insert(subnet, 0);
+            }
+            for (_, subnet) in slot_generator.get_aggregates(slot, &all_validators) {
+                *subnet_aggregators.get_mut(&subnet).unwrap() += 1;
+            }
+            counts.extend(subnet_aggregators.into_values());
+        }
+        counts
+    };
+
+    // 128 members per committee and a target of 16 give a modulo of 8.
+    let counts = aggregators_per_committee(&slot_generator(16, 1));
+    let average = counts.iter().sum::<usize>() as f64 / counts.len() as f64;
+    assert!((average - 16.0).abs() < 2.0, "average of {average}");
+    assert!(
+        counts.iter().any(|count| *count != counts[0]),
+        "counts vary"
+    );
+
+    // A target of 1 makes every member aggregate with a 1/128 chance, leaving some committees
+    // without aggregators.
+    let counts = aggregators_per_committee(&slot_generator(1, 1));
+    assert!(counts.contains(&0));
+    assert!(counts.iter().any(|count| *count > 1));
+
+    // Selection is seeded.
+    assert_eq!(
+        aggregators_per_committee(&slot_generator(16, 2)),
+        aggregators_per_committee(&slot_generator(16, 2))
+    );
+}
+
+#[test]
+fn test_aggregators_attest_in_their_slot() {
+    let slots_per_epoch = 32;
+    let total_validators = 16384;
+    let attestation_subnets = 64;
+    let all_validators = (0..total_validators).map(ValId).collect();
+
+    for duty_mode in [DutyMode::Simple, DutyMode::Spec] {
+        for aggregator_selection in [AggregatorSelection::Fixed, AggregatorSelection::Modulo] {
+            let slot_generator = SlotGenerator::new(
+                slots_per_epoch,
+                attestation_subnets,
+                1,
+                1,
+                16,
+                total_validators,
+            )
+            .with_duty_mode(duty_mode)
+            .with_aggregator_selection(aggregator_selection);
+
+            let mut previous_aggregators = BTreeSet::new();
+            for slot in Slot::new(0)
+                .epoch(slots_per_epoch)
+                .slot_iter(slots_per_epoch)
+            {
+                let attesters: BTreeSet<_> = slot_generator
+                    .get_attestations(slot, &all_validators)
+                    .collect();
+                let aggregators: BTreeSet<_> = slot_generator
+                    .get_aggregates(slot, &all_validators)
+                    .collect();
+                assert!(!aggregators.is_empty());
+                assert!(
+                    aggregators.is_subset(&attesters),
+                    "aggregators attest in the same slot and subnet"
+                );
+                assert!(
+                    aggregators.is_disjoint(&previous_aggregators),
+                    "aggregators rotate every slot"
+                );
+                previous_aggregators = aggregators;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_simple_duties_spread_local_validators() {
+    let slots_per_epoch = 32;
+    let total_validators = 16384;
+    let attestation_subnets = 64;
+    // A node runs a contiguous range of validators.
+    let local_validators = (0..1000).map(ValId).collect();
+    let slot_generator = SlotGenerator::new(
+        slots_per_epoch,
+        attestation_subnets,
+        1,
+        1,
+        16,
+        total_validators,
+    );
+
+    let mut slots = BTreeSet::new();
+    let mut subnets = BTreeSet::new();
+    for slot in Slot::new(0)
+        .epoch(slots_per_epoch)
+        .slot_iter(slots_per_epoch)
+    {
+        for (_, subnet) in slot_generator.get_attestations(slot, &local_validators) {
+            slots.insert(slot);
+            subnets.insert(subnet);
+        }
+    }
+    // The local validators attest in every slot of the epoch and in every subnet.
+    assert_eq!(slots.len() as u64, slots_per_epoch);
+    assert_eq!(subnets.len() as u64, attestation_subnets);
+}
+
+#[test]
+fn test_balance_weighted_proposers() {
+    let slots_per_epoch = 32;
+    let total_validators = 8;
+    let all_validators = (0..total_validators).map(ValId).collect();
+    // Validator 0 holds as much stake as all others together.
+    let stake = spec::MAX_EFFECTIVE_BALANCE_ELECTRA / total_validators;
+    let mut effective_balances = vec![stake; total_validators as usize];
+    effective_balances[0] = stake * (total_validators - 1);
+    let slot_generator = |effective_balances: &Vec<u64>| {
+        SlotGenerator::new(slots_per_epoch, 1, 1, 1, 1, total_validators)
+            .with_proposer_selection(ProposerSelection::BalanceWeighted)
+            .with_effective_balances(effective_balances.clone())
+            .with_seed(7)
+    };
+    let proposers = |slot_generator: &SlotGenerator| -> Vec<ValId> {
+        (0..256)
+            .map(|slot| {
+                slot_generator
+                    .get_blocks(Slot::new(slot), &all_validators)
+                    .expect("every slot has a proposer")
+            })
+            .collect()
+    };
+
+    let heavy_proposals = proposers(&slot_generator(&effective_balances))
+        .into_iter()
+        .filter(|proposer| *proposer == ValId(0))
+        .count();
+    // Half of the stake, though the spec's sampling favours heavy validators a bit further.
+    assert!((128..192).contains(&heavy_proposals), "{heavy_proposals}");
+    assert_eq!(
+        proposers(&slot_generator(&effective_balances)),
+        proposers(&slot_generator(&effective_balances))
+    );
+
+    // Validators without stake are only picked when the random value is zero, as in the spec.
+    let effective_balances = (0..total_validators)
+        .map(|val_id| val_id % 2 * stake)
+        .collect();
+    let unstaked_proposals = proposers(&slot_generator(&effective_balances))
+        .into_iter()
+        .filter(|proposer| **proposer % 2 == 0)
+        .count();
+    assert!(unstaked_proposals <= 2, "{unstaked_proposals}");
+
+    let build = |effective_balances| {
+        GeneratorBuilder::<ManualSlotClock>::default()
+            .total_validators(total_validators)
+            .attestation_subnets(1)
+            .sync_subnet_size(1)
+            .target_aggregators(1)
+            .effective_balances(effective_balances)
+            .build(HashSet::new())
+    };
+    assert!(build(vec![spec::MAX_EFFECTIVE_BALANCE; total_validators as usize]).is_ok());
+    for effective_balances in [
+        vec![spec::MAX_EFFECTIVE_BALANCE; total_validators as usize - 1],
+        vec![spec::MAX_EFFECTIVE_BALANCE_ELECTRA + 1; total_validators as usize],
+        vec![0; total_validators as usize],
+    ] {
+        assert!(build(effective_balances).is_err());
+    }
+}
+
+#[test]
+fn test_balance_weighted_sync_committee() {
+    let slots_per_epoch = 32;
+    let sync_subnet_size = 4;
+    let sync_committee_subnets = 4;
+    let total_validators = 8;
+    let all_validators = (0..total_validators).map(ValId).collect();
+    let slot_generator = SlotGenerator::new(
+        slots_per_epoch,
+        1,
+        sync_subnet_size,
+        sync_committee_subnets,
+        1,
+        total_validators,
+    )
+    .with_sync_committee_selection(SyncCommitteeSelection::BalanceWeighted)
+    .with_effective_balances(vec![
+        spec::MAX_EFFECTIVE_BALANCE_ELECTRA;
+        total_validators as usize
+    ]);
+
+    // Twice as many positions as validators, so members hold several of them.
+    let messages: Vec<_> = slot_generator
+        .get_sync_committee_messages(Slot::new(0), &all_validators)
+        .collect();
+    assert_eq!(
+        messages.len() as u64,
+        sync_subnet_size * sync_committee_subnets
+    );
+    let mut positions = HashMap::<ValId, u64>::new();
+    let mut subnet_sizes = HashMap::<Subnet, u64>::new();
+    for (val_id, subnet) in &messages {
+        *positions.entry(*val_id).or_default() += 1;
+        *subnet_sizes.entry(*subnet).or_default() += 1;
+    }
+    assert!(positions.values().any(|count| *count > 1));
+    assert_eq!(subnet_sizes.len() as u64, sync_committee_subnets);
+    assert!(subnet_sizes.values().all(|size| *size == sync_subnet_size));
+
+    // Only messages of our own validators are returned.
+    let (some_validators, _): (BTreeSet<_>, BTreeSet<_>) =
+        all_validators.iter().partition(|val_id| ***val_id < 4);
+    assert_eq!(
+        slot_generator
+            .get_sync_committee_messages(Slot::new(0), &some_validators)
+            .collect::<Vec<_>>(),
+        messages
+            .iter()
+            .copied()
+            .filter(|(val_id, _)| some_validators.contains(val_id))
+            .collect::<Vec<_>>()
+    );
+
+    // One aggregator per subnet, among the members of that subnet.
+    let aggregates: Vec<_> = slot_generator
+        .get_sync_committee_aggregates(Slot::new(0), &all_validators)
+        .collect();
+    assert_eq!(aggregates.len() as u64, sync_committee_subnets);
+    assert!(aggregates
+        .iter()
+        .all(|aggregate| messages.contains(aggregate)));
+
+    // The committee is kept for the whole period.
+    let next_period = Slot::new(slots_per_epoch * crate::EPOCHS_PER_SYNC_COMMITTEE_PERIOD);
+    assert_eq!(
+        slot_generator
+            .get_sync_committee_messages(next_period - 1, &all_validators)
+            .collect::<Vec<_>>(),
+        messages
+    );
+    assert_ne!(
+        slot_generator
+            .get_sync_committee_messages(next_period, &all_validators)
+            .collect::<Vec<_>>(),
+        messages
+    );
+}
+
+#[test]
+fn test_modulo_sync_aggregator_selection() {
+    let slots_per_epoch = 32;
+    let sync_subnet_size = 128;
+    let sync_committee_subnets = 4;
+    let total_validators = 1024;
+    let all_validators = (0..total_validators).map(ValId).collect();
+    let slot_generator = |target_sync_aggregators, seed| {
+        SlotGenerator::new(
+            slots_per_epoch,
+            64,
+            sync_subnet_size,
+            sync_committee_subnets,
+            1,
+            total_validators,
+        )
+        .with_aggregator_selection(AggregatorSelection::Modulo)
+        .with_target_sync_aggregators(target_sync_aggregators)
+        .with_seed(seed)
+    };
+    let aggregators_per_subnet = |slot_generator: &SlotGenerator| -> Vec<u64> {
+        let mut counts = Vec::new();
+        for slot in Slot::new(0)
+            .epoch(slots_per_epoch)
+            .slot_iter(slots_per_epoch)
+        {
+            let members: BTreeSet<_> = slot_generator
+                .get_sync_committee_messages(slot, &all_validators)
+                .collect();
+            let mut slot_counts = vec![0; sync_committee_subnets as usize];
+            for aggregate in slot_generator.get_sync_committee_aggregates(slot, &all_validators) {
+                assert!(members.contains(&aggregate));
+                slot_counts[*aggregate.1 as usize] += 1;
+            }
+            counts.extend(slot_counts);
+        }
+        counts
+    };
+
+    // Members aggregate with a 16/128 chance, regardless of the attestation target.
+    let counts = aggregators_per_subnet(&slot_generator(16, 1));
+    let mean = counts.iter().sum::<u64>() as f64 / counts.len() as f64;
+    assert!((12.0..20.0).contains(&mean), "{mean}");
+    assert!(counts.iter().any(|count| *count != 16));
+
+    // Selection is seeded and changes every slot.
+    assert_eq!(counts, aggregators_per_subnet(&slot_generator(16, 1)));
+    let first_slot: BTreeSet<_> = slot_generator(16, 1)
+        .get_sync_committee_aggregates(Slot::new(0), &all_validators)
+        .collect();
+    let second_slot: BTreeSet<_> = slot_generator(16, 1)
+        .get_sync_committee_aggregates(Slot::new(1), &all_validators)
+        .collect();
+    assert_ne!(first_slot, second_slot);
+
+    // A target as large as the subnet makes every member aggregate.
+    assert!(aggregators_per_subnet(&slot_generator(sync_subnet_size, 1))
+        .into_iter()
+        .all(|count| count == sync_subnet_size));
+}
+
+#[test]
+fn test_target_aggregators_validation() {
+    let builder = |target_attestation_aggregators, target_sync_aggregators| {
+        let mut builder = GeneratorBuilder::<ManualSlotClock>::default();
+        builder
+            .total_validators(1024)
+            .attestation_subnets(64)
+            .sync_subnet_size(64)
+            .sync_committee_subnets(4)
+            .target_attestation_aggregators(target_attestation_aggregators)
+            .target_sync_aggregators(target_sync_aggregators);
+        builder
+    };
+
+    assert!(builder(16, 16).build(HashSet::new()).is_ok());
+    assert!(builder(16, 64).build(HashSet::new()).is_ok());
+    // Aggregators are needed for both.
+    assert!(builder(0, 16).build(HashSet::new()).is_err());
+    assert!(builder(16, 0).build(HashSet::new()).is_err());
+    // With 32 slots and a single committee per slot, committees have 32 members.
+    assert!(builder(32, 16).build(HashSet::new()).is_ok());
+    assert!(builder(33, 16).build(HashSet::new()).is_err());
+    // More aggregators than members of the sync subnet.
+    assert!(builder(16, 65).build(HashSet::new()).is_err());
+    // Balance weighted sync committees can hold a validator several times, so they are not
+    // limited by the number of validators.
+    assert!(builder(1, 64)
+        .total_validators(255)
+        .sync_committee_selection(SyncCommitteeSelection::BalanceWeighted)
+        .build(HashSet::new())
+        .is_ok());
+    // Rotating sync committees are.
+    assert!(builder(1, 64)
+        .total_validators(255)
+        .sync_committee_selection(SyncCommitteeSelection::Rotating)
+        .build(HashSet::new())
+        .is_err());
+
+    // Both targets can be set at once.
+    let mut builder = builder(0, 0);
+    assert!(builder.target_aggregators(8).build(HashSet::new()).is_ok());
+}
+
+#[test]
+fn test_epoch_duties_split_across_nodes() {
+    let slots_per_epoch = 32;
+    let total_validators = 4096;
+    // Every other validator, so that duties of other nodes are left out, split in two nodes.
+    let validators: BTreeSet<_> = (0..total_validators).step_by(2).map(ValId).collect();
+    let (first_node, second_node): (BTreeSet<_>, BTreeSet<_>) =
+        validators.iter().partition(|val_id| ***val_id % 4 == 0);
+    let count = |messages: &mut dyn Iterator<Item = Message>| {
+        let mut counts = HashMap::<Message, usize>::new();
+        for message in messages {
+            *counts.entry(message).or_default() += 1;
+        }
+        counts
+    };
+
+    let configurations = [DutyMode::Simple, DutyMode::Spec]
+        .into_iter()
+        .zip([Fork::Deneb, Fork::Fulu]);
+    for (duty_mode, fork) in configurations {
+        for aggregator_selection in [AggregatorSelection::Fixed, AggregatorSelection::Modulo] {
+            for sync_committee_selection in [
+                SyncCommitteeSelection::Rotating,
+                SyncCommitteeSelection::BalanceWeighted,
+            ] {
+                let slot_generator =
+                    SlotGenerator::new(slots_per_epoch, 64, 128, 4, 16, total_validators)
+                        .with_duty_mode(duty_mode)
+                        .with_aggregator_selection(aggregator_selection)
+                        .with_sync_committee_selection(sync_committee_selection)
+                        .with_blob_count(BlobCount::Uniform { max: 6 })
+                        .with_fork_schedule(ForkSchedule::from_genesis(fork))
+                        .with_operation_rates(HashMap::from([
+                            (MsgType::VoluntaryExit, 3.0),
+                            (MsgType::BlsToExecutionChange, 1.0),
+                        ]))
+                        .with_light_client_server(true)
+                        .with_effective_balances(vec![
+                            spec::MAX_EFFECTIVE_BALANCE_ELECTRA;
+                            total_validators as usize
+                        ])
+                        .with_seed(3);
+                let epoch = Slot::new(0).epoch(slots_per_epoch) + 1;
+                let duties = slot_generator.epoch_duties(epoch, &validators);
+                let first_duties = slot_generator.epoch_duties(epoch, &first_node);
+                let second_duties = slot_generator.epoch_duties(epoch, &second_node);
+                assert_eq!(duties.epoch(), epoch);
+                // Operations are drawn among each node's validators, and light client updates
+                // are sent by every node.
+                let validator_duties = MsgType::iter().filter(|msg_type| {
+                    !msg_type.is_operation()
+                        && !matches!(
+                            msg_type,
+                            MsgType::LightClientFinalityUpdate
+                                | MsgType::LightClientOptimisticUpdate
+                        )
+                });
+                for msg_type in validator_duties {
+                    for slot in epoch.slot_iter(slots_per_epoch) {
+                        let mut split = first_duties
+                            .messages(msg_type, slot)
+                            .chain(second_duties.messages(msg_type, slot));
+                        assert!(
+                            count(&mut duties.messages(msg_type, slot)) == count(&mut split),
+                            "{msg_type} messages of {duty_mode:?}, {aggregator_selection:?}, \
+                             {sync_committee_selection:?}, {fork:?} in {slot:?} \
+                             differ"
+                        );
+                        // The per-slot getters read the same duties.
+                        assert!(duties
+                            .messages(msg_type, slot)
+                            .eq(slot_generator.get_messages(msg_type, slot, &validators)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_blob_sidecars() {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    let slots_per_epoch = 32;
+    let total_validators = 64;
+    let all_validators = (0..total_validators).map(ValId).collect();
+    let slot_generator = |blob_count| {
+        SlotGenerator::new(slots_per_epoch, 1, 1, 1, 1, total_validators)
+            .with_blob_count(blob_count)
+    };
+    let blobs_per_slot = |slot_generator: &SlotGenerator| -> Vec<u64> {
+        (0..256)
+            .map(|slot| {
+                let slot = Slot::new(slot);
+                let proposer = slot_generator.get_blocks(slot, &all_validators).unwrap();
+                let mut blobs = 0;
+                for message in
+                    slot_generator.get_messages(MsgType::BlobSidecar, slot, &all_validators)
+                {
+                    let Message::BlobSidecar {
+                        proposer: blob_proposer,
+                        index,
+                        subnet,
+                        slot: blob_slot,
+                    } = message
+                    else {
+                        panic!("only blob sidecars are returned");
+                    };
+                    assert_eq!((blob_proposer, index, blob_slot), (proposer, blobs, slot));
+                    assert_eq!(subnet, Subnet(index % spec::BLOB_SIDECAR_SUBNET_COUNT));
+                    assert_eq!(
+                        message
+                            .payload(Fork::Deneb, &mut ChaCha8Rng::seed_from_u64(0))
+                            .len(),
+                        131_928
+                    );
+                    blobs += 1;
+                }
+                blobs
+            })
+            .collect()
+    };
+
+    // No blobs by default.
+    assert!(blobs_per_slot(&SlotGenerator::new(
+        slots_per_epoch,
+        1,
+        1,
+        1,
+        1,
+        total_validators
+    ))
+    .into_iter()
+    .all(|blobs| blobs == 0));
+    assert!(blobs_per_slot(&slot_generator(BlobCount::Fixed(3)))
+        .into_iter()
+        .all(|blobs| blobs == 3));
+    let uniform = blobs_per_slot(&slot_generator(BlobCount::Uniform { max: 6 }));
+    assert!(uniform.iter().all(|blobs| *blobs <= 6));
+    assert!(uniform.contains(&0) && uniform.contains(&6));
+    // The count of each slot does not change between lookups.
+    assert_eq!(
+        uniform,
+        blobs_per_slot(&slot_generator(BlobCount::Uniform { max: 6 }))
+    );
+
+    // Blobs are only sent by the proposer.
+    let others = (1..total_validators).map(ValId).collect();
+    assert_eq!(
+        slot_generator(BlobCount::Fixed(3))
+            .get_blob_sidecars(Slot::new(0), &others)
+            .count(),
+        0
+    );
+
+    assert!(GeneratorBuilder::<ManualSlotClock>::default()
+        .total_validators(total_validators)
+        .attestation_subnets(1)
+        .sync_subnet_size(1)
+        .target_aggregators(1)
+        .blob_count(BlobCount::Uniform {
+            max: spec::MAX_BLOBS_PER_BLOCK + 1
+        })
+        .build(HashSet::new())
+        .is_err());
+
+    // Electra raises the limit, and adds subnets for the extra blobs.
+    let electra_epoch = types::Epoch::new(1);
+    let fork_schedule =
+        ForkSchedule::from_genesis(Fork::Deneb).with_fork_epoch(Fork::Electra, electra_epoch);
+    let slot_generator = slot_generator(BlobCount::Fixed(spec::MAX_BLOBS_PER_BLOCK_ELECTRA))
+        .with_fork_schedule(fork_schedule.clone());
+    for slot in [Slot::new(0), electra_epoch.start_slot(slots_per_epoch)] {
+        let subnets: Vec<_> = slot_generator
+            .get_blob_sidecars(slot, &all_validators)
+            .map(|(_, _, subnet)| *subnet)
+            .collect();
+        let fork = slot_generator.fork_at(slot);
+        assert_eq!(subnets.len() as u64, fork.max_blobs_per_block());
+        assert!(subnets
+            .iter()
+            .all(|subnet| *subnet < fork.blob_sidecar_subnet_count()));
+    }
+    assert_eq!(Fork::Electra.blob_sidecar_subnet_count(), 9);
+    let builder = |max| {
+        let mut builder = GeneratorBuilder::<ManualSlotClock>::default();
+        builder
+            .total_validators(total_validators)
+            .attestation_subnets(1)
+            .sync_subnet_size(1)
+            .target_aggregators(1)
+            .fork_schedule(fork_schedule.clone())
+            .blob_count(BlobCount::Uniform { max });
+        builder
+    };
+    assert!(builder(spec::MAX_BLOBS_PER_BLOCK_ELECTRA)
+        .build(HashSet::new())
+        .is_ok());
+    assert!(builder(spec::MAX_BLOBS_PER_BLOCK_ELECTRA + 1)
+        .build(HashSet::new())
+        .is_err());
+}
+
+#[test]
+fn test_data_column_sidecars() {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    let slots_per_epoch = 32;
+    let total_validators = 64;
+    let all_validators = (0..total_validators).map(ValId).collect();
+    let slot_generator = |blob_count| {
+        SlotGenerator::new(slots_per_epoch, 1, 1, 1, 1, total_validators)
+            .with_blob_count(blob_count)
+            .with_fork_schedule(ForkSchedule::from_genesis(Fork::Fulu))
+    };
+
+    let slot = Slot::new(5);
+    let proposer = slot_generator(BlobCount::Fixed(3))
+        .get_blocks(slot, &all_validators)
+        .unwrap();
+    let columns: Vec<_> = slot_generator(BlobCount::Fixed(3))
+        .get_messages(MsgType::DataColumnSidecar, slot, &all_validators)
+        .collect();
+    assert_eq!(columns.len() as u64, spec::NUMBER_OF_COLUMNS);
+    for (column, message) in columns.iter().enumerate() {
+        assert!(
+            *message
+                == Message::DataColumnSidecar {
+                    proposer,
+                    index: column as u64,
+                    subnet: Subnet(column as u64),
+                    blob_count: 3,
+                    slot,
+                }
+        );
+    }
+    // Columns carry a cell of each blob.
+    let payload_len = |message: &Message| {
+        message
+            .payload(Fork::Fulu, &mut ChaCha8Rng::seed_from_u64(0))
+            .len()
+    };
+    assert_eq!(payload_len(&columns[0]), 356 + 3 * 2144);
+    let six_blob_columns: Vec<_> = slot_generator(BlobCount::Fixed(6))
+        .get_messages(MsgType::DataColumnSidecar, slot, &all_validators)
+        .collect();
+    assert_eq!(payload_len(&six_blob_columns[0]), 356 + 6 * 2144);
+
+    // Columns replace blob sidecars, and are not sent for blocks without blobs.
+    assert_eq!(
+        slot_generator(BlobCount::Fixed(3))
+            .get_messages(MsgType::BlobSidecar, slot, &all_validators)
+            .count(),
+        0
+    );
+    assert_eq!(
+        slot_generator(BlobCount::Fixed(0))
+            .get_messages(MsgType::DataColumnSidecar, slot, &all_validators)
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn test_custody_columns() {
+    let node_id = [7; 32];
+    let groups = spec::get_custody_groups(node_id, 8);
+    assert_eq!(groups.len(), 8);
+    assert!(groups.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(groups, spec::get_custody_groups(node_id, 8));
+    assert_ne!(groups, spec::get_custody_groups([8; 32], 8));
+    // Larger custody extends the smaller one.
+    let more_groups = spec::get_custody_groups(node_id, 16);
+    assert!(groups.iter().all(|group| more_groups.contains(group)));
+    // The largest id wraps around.
+    assert_eq!(spec::get_custody_groups([u8::MAX; 32], 4).len(), 4);
+
+    let total_validators = 4096;
+    let build = |validators: u64, custody_group_count: Option<u64>| {
+        let mut builder = GeneratorBuilder::<ManualSlotClock>::default();
+        builder.total_validators(total_validators).node_id(node_id);
+        if let Some(custody_group_count) = custody_group_count {
+            builder.custody_group_count(custody_group_count);
+        }
+        builder.build((0..validators).map(ValId).collect())
+    };
+
+    // Custody grows with the balance of the node's validators.
+    for (validators, custody_group_count) in [(0, 4), (1, 8), (10, 10), (200, 128)] {
+        let generator = build(validators, None).unwrap();
+        assert_eq!(generator.node_id(), node_id);
+        assert_eq!(
+            generator.custody_columns().len() as u64,
+            custody_group_count
+        );
+        assert_eq!(
+            generator.data_column_subnets().len() as u64,
+            custody_group_count
+        );
+    }
+    assert_eq!(
+        build(0, Some(8)).unwrap().custody_columns(),
+        groups.as_slice()
+    );
+    assert!(build(0, Some(3)).is_err());
+    assert!(build(0, Some(129)).is_err());
+}
+
+#[test]
+fn test_operations() {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    let slots_per_epoch = 8;
+    let total_validators = 64;
+    let validators: BTreeSet<_> = (0..total_validators).step_by(2).map(ValId).collect();
+    let slot_generator = SlotGenerator::new(slots_per_epoch, 1, 1, 1, 1, total_validators)
+        .with_operation_rates(HashMap::from([
+            (MsgType::VoluntaryExit, 2.0),
+            (MsgType::AttesterSlashing, 0.5),
+        ]));
+
+    let epochs = 256;
+    let mut exits_per_epoch = Vec::new();
+    let mut slashings = 0;
+    for epoch in 0..epochs {
+        let epoch = Slot::new(epoch * slots_per_epoch).epoch(slots_per_epoch);
+        let duties = slot_generator.epoch_duties(epoch, &validators);
+        let mut exits = 0;
+        let mut exited = BTreeSet::new();
+        let mut slashed = BTreeSet::new();
+        for slot in epoch.slot_iter(slots_per_epoch) {
+            for msg_type in MsgType::iter().filter(MsgType::is_operation) {
+                for message in duties.messages(msg_type, slot) {
+                    assert_eq!(message.slot(), slot);
+                    assert_eq!(message.msg_type().phase(), SlotPhase::Block);
+                    match message {
+                        Message::VoluntaryExit { validator, .. } => {
+                            assert!(validators.contains(&validator));
+                            assert_eq!(
+                                message
+                                    .payload(Fork::Deneb, &mut ChaCha8Rng::seed_from_u64(0))
+                                    .len(),
+                                112
+                            );
+                            // Validators exit once.
+                            assert!(exited.insert(validator));
+                            exits += 1;
+                        }
+                        Message::AttesterSlashing { validator, .. } => {
+                            assert!(validators.contains(&validator));
+                            // Repeated slashings would be dropped by gossip.
+                            assert!(slashed.insert((slot, validator)));
+                            slashings += 1;
+                        }
+                        _ => panic!("only configured operations are sent"),
+                    }
+                }
+            }
+        }
+        exits_per_epoch.push(exits);
+    }
+
+    let mean_exits = exits_per_epoch.iter().sum::<u64>() as f64 / epochs as f64;
+    assert!((1.7..2.3).contains(&mean_exits), "{mean_exits}");
+    // Some epochs go without exits and others get bursts of them.
+    assert!(exits_per_epoch.contains(&0));
+    assert!(exits_per_epoch.iter().any(|exits| *exits >= 5));
+    let mean_slashings = slashings as f64 / epochs as f64;
+    assert!((0.35..0.65).contains(&mean_slashings), "{mean_slashings}");
+
+    // Rates beyond what the validators can send are capped: each exits at most once.
+    let slot_generator = SlotGenerator::new(slots_per_epoch, 1, 1, 1, 1, total_validators)
+        .with_operation_rates(HashMap::from([(MsgType::VoluntaryExit, 1000.0)]));
+    let epoch = Slot::new(0).epoch(slots_per_epoch);
+    let duties = slot_generator.epoch_duties(epoch, &validators);
+    let exited: Vec<_> = epoch
+        .slot_iter(slots_per_epoch)
+        .flat_map(|slot| duties.messages(MsgType::VoluntaryExit, slot))
+        .collect();
+    assert_eq!(exited.len(), validators.len());
+
+    for (msg_type, rate) in [
+        (MsgType::Attestation, 1.0),
+        (MsgType::VoluntaryExit, -1.0),
+        (MsgType::VoluntaryExit, f64::NAN),
+        (MsgType::VoluntaryExit, f64::INFINITY),
+    ] {
+        assert!(GeneratorBuilder::<ManualSlotClock>::default()
+            .total_validators(4096)
+            .operation_rate(msg_type, rate)
+            .build(HashSet::new())
+            .is_err());
+    }
+    // The slot generator checks the rates it is given too.
+    for rate in [-1.0, f64::NAN, f64::INFINITY] {
+        assert!(std::panic::catch_unwind(|| {
+            SlotGenerator::new(slots_per_epoch, 1, 1, 1, 1, total_validators)
+                .with_operation_rates(HashMap::from([(MsgType::VoluntaryExit, rate)]))
+        })
+        .is_err());
+    }
+}
+
+#[test]
+fn test_light_client_updates() {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    let total_validators = 4096;
+    let slots_per_epoch = 8;
+    let build = |fork, serve_light_clients| {
+        Generator::builder()
+            .total_validators(total_validators)
+            .slots_per_epoch(slots_per_epoch)
+            .fork(fork)
+            .serve_light_clients(serve_light_clients)
+            .build((0..total_validators).map(ValId).collect())
+            .unwrap()
+    };
+    let slots = Slot::new(0)..Slot::new(slots_per_epoch);
+    let is_update = |message: &Message| {
+        matches!(
+            message,
+            Message::LightClientFinalityUpdate { .. } | Message::LightClientOptimisticUpdate { .. }
+        )
+    };
+
+    assert!(!build(Fork::Deneb, false)
+        .messages_for_slots(slots.clone())
+        .any(|timed| is_update(&timed.message)));
+    // Light clients came with Altair.
+    assert!(!build(Fork::Phase0, true)
+        .messages_for_slots(slots.clone())
+        .any(|timed| is_update(&timed.message)));
+
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let mut update_sizes = BTreeMap::new();
+    for fork in [Fork::Altair, Fork::Capella, Fork::Deneb, Fork::Electra] {
+        let messages: Vec<_> = build(fork, true)
+            .messages_for_slots(slots.clone())
+            .collect();
+        for slot in slots.start.as_u64()..slots.end.as_u64() {
+            let slot_messages: Vec<_> = messages
+                .iter()
+                .filter(|timed| timed.message.slot() == slot)
+                .map(|timed| &timed.message)
+                .collect();
+            let updates: Vec<_> = slot_messages
+                .iter()
+                .enumerate()
+                .filter(|(_, message)| is_update(message))
+                .collect();
+            assert_eq!(updates.len(), 2, "one update of each kind per slot");
+            // Updates follow the sync committee aggregates of their slot.
+            let last_contribution = slot_messages
+                .iter()
+                .rposition(|message| matches!(message, Message::SignedContributionAndProof { .. }));
+            assert!(last_contribution < Some(updates[0].0));
+            for (_, message) in updates {
+                assert_eq!(message.msg_type().phase(), SlotPhase::Aggregate);
+                update_sizes
+                    .entry((fork, message.msg_type()))
+                    .or_insert_with(BTreeSet::new)
+                    .insert(message.payload(fork, &mut rng).len());
+            }
+        }
+    }
+
+    let sizes = |fork, msg_type| &update_sizes[&(fork, msg_type)];
+    let finality = MsgType::LightClientFinalityUpdate;
+    let optimistic = MsgType::LightClientOptimisticUpdate;
+    assert_eq!(sizes(Fork::Altair, finality), &BTreeSet::from([584]));
+    assert_eq!(sizes(Fork::Altair, optimistic), &BTreeSet::from([280]));
+    // Execution payload headers make updates larger since Capella, Deneb adds the blob gas
+    // fields and Electra deepens the finality branch.
+    assert_eq!(sizes(Fork::Capella, finality), &BTreeSet::from([2056]));
+    assert_eq!(sizes(Fork::Capella, optimistic), &BTreeSet::from([1016]));
+    assert_eq!(sizes(Fork::Deneb, finality), &BTreeSet::from([2088]));
+    assert_eq!(sizes(Fork::Deneb, optimistic), &BTreeSet::from([1032]));
+    assert_eq!(sizes(Fork::Electra, finality), &BTreeSet::from([2120]));
+    assert_eq!(sizes(Fork::Electra, optimistic), &BTreeSet::from([1032]));
+}
+
+#[test]
+fn test_fork_schedule() {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use types::Epoch;
+
+    let total_validators = 4096;
+    let slots_per_epoch = 8;
+    let fork_schedule = ForkSchedule::from_genesis(Fork::Phase0)
+        .with_fork_epoch(Fork::Altair, Epoch::new(1))
+        .with_fork_epoch(Fork::Bellatrix, Epoch::new(2))
+        .with_fork_epoch(Fork::Capella, Epoch::new(2))
+        .with_fork_epoch(Fork::Deneb, Epoch::new(2))
+        .with_fork_epoch(Fork::Electra, Epoch::new(3))
+        .with_fork_epoch(Fork::Fulu, Epoch::new(4));
+    assert_eq!(fork_schedule.fork_at_epoch(Epoch::new(0)), Fork::Phase0);
+    assert_eq!(fork_schedule.fork_at_epoch(Epoch::new(2)), Fork::Deneb);
+    assert_eq!(fork_schedule.fork_at_epoch(Epoch::new(100)), Fork::Fulu);
+
+    let generator = Generator::builder()
+        .total_validators(total_validators)
+        .slots_per_epoch(slots_per_epoch)
+        .blob_count(BlobCount::Fixed(1))
+        .operation_rate(MsgType::BlsToExecutionChange, 20.0)
+        .fork_schedule(fork_schedule.clone())
+        .seed(1)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+    assert_eq!(generator.fork_schedule(), &fork_schedule);
+
+    let mut types_per_epoch = BTreeMap::<u64, BTreeSet<MsgType>>::new();
+    let mut attestation_sizes = BTreeMap::<u64, BTreeSet<usize>>::new();
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    for TimedMessage { message, .. } in
+        generator.messages_for_slots(Slot::new(0)..Slot::new(5 * slots_per_epoch))
+    {
+        let epoch = message.slot().epoch(slots_per_epoch).as_u64();
+        types_per_epoch
+            .entry(epoch)
+            .or_default()
+            .insert(message.msg_type());
+        if message.msg_type() == MsgType::Attestation {
+            let fork = fork_schedule.fork_at_epoch(Epoch::new(epoch));
+            attestation_sizes
+                .entry(epoch)
+                .or_default()
+                .insert(message.payload(fork, &mut rng).len());
+        }
+    }
+
+    // Message types turn on and off exactly at the fork epochs.
+    let sent_in = |epoch: u64, msg_type| types_per_epoch[&epoch].contains(&msg_type);
+    for epoch in 0..5 {
+        assert_eq!(sent_in(epoch, MsgType::SyncCommitteeMessage), epoch >= 1);
+        assert_eq!(
+            sent_in(epoch, MsgType::SignedContributionAndProof),
+            epoch >= 1
+        );
+        assert_eq!(
+            sent_in(epoch, MsgType::BlobSidecar),
+            (2..4).contains(&epoch)
+        );
+        assert_eq!(sent_in(epoch, MsgType::DataColumnSidecar), epoch >= 4);
+        assert_eq!(sent_in(epoch, MsgType::BlsToExecutionChange), epoch >= 2);
+        assert!(sent_in(epoch, MsgType::BeaconBlock));
+        assert!(sent_in(epoch, MsgType::Attestation));
+    }
+    // Attestations carry their aggregation bits until Electra, and are sent as fixed size single
+    // attestations since.
+    assert!(attestation_sizes[&2].len() > 1);
+    assert_eq!(attestation_sizes[&3], BTreeSet::from([240]));
+
+    // Payloads follow the fork of the message's slot.
+    let block = |slot| Message::BeaconBlock {
+        proposer: ValId(0),
+        slot: Slot::new(slot),
+    };
+    assert!(generator.payload(&block(0)).len() < 20_000);
+    assert!(generator.payload(&block(2 * slots_per_epoch)).len() >= 30_000);
+
+    for fork_schedule in [
+        ForkSchedule::from_genesis(Fork::Deneb).with_fork_epoch(Fork::Phase0, Epoch::new(1)),
+        // Forks must be scheduled in order.
+        ForkSchedule::from_genesis(Fork::Altair).with_fork_epoch(Fork::Capella, Epoch::new(3)),
+        ForkSchedule::from_genesis(Fork::Phase0)
+            .with_fork_epoch(Fork::Altair, Epoch::new(5))
+            .with_fork_epoch(Fork::Bellatrix, Epoch::new(4)),
+    ] {
+        assert!(GeneratorBuilder::<ManualSlotClock>::default()
+            .total_validators(total_validators)
+            .fork_schedule(fork_schedule)
+            .build(HashSet::new())
+            .is_err());
+    }
+}
+
+#[test]
+fn test_gossip_topics() {
+    use types::Epoch;
+
+    let mainnet_genesis_validators_root = [
+        0x4b, 0x36, 0x3d, 0xb9, 0x4e, 0x28, 0x61, 0x20, 0xd7, 0x6e, 0xb9, 0x05, 0x34, 0x0f, 0xdd,
+        0x4e, 0x54, 0xbf, 0xe9, 0xf0, 0x6b, 0xf3, 0x3f, 0xf6, 0xcf, 0x5a, 0xd2, 0x7f, 0x51, 0x1b,
+        0xfe, 0x95,
+    ];
+    let slots_per_epoch = 32;
+    let fork_schedule = ForkSchedule::from_genesis(Fork::Phase0)
+        .with_fork_epoch(Fork::Altair, Epoch::new(1))
+        .with_fork_epoch(Fork::Bellatrix, Epoch::new(2))
+        .with_fork_epoch(Fork::Capella, Epoch::new(3))
+        .with_fork_epoch(Fork::Deneb, Epoch::new(4))
+        .with_fork_epoch(Fork::Electra, Epoch::new(5));
+    let fork_context = ForkContext::new(
+        fork_schedule,
+        slots_per_epoch,
+        mainnet_genesis_validators_root,
+    );
+
+    // Digests of mainnet.
+    for (fork, digest) in [
+        (Fork::Phase0, [0xb5, 0x30, 0x3f, 0x2a]),
+        (Fork::Altair, [0xaf, 0xca, 0xab, 0xa0]),
+        (Fork::Bellatrix, [0x4a, 0x26, 0xc5, 0x8b]),
+        (Fork::Capella, [0xbb, 0xa4, 0xda, 0x96]),
+        (Fork::Deneb, [0x6a, 0x95, 0xa1, 0xa9]),
+        (Fork::Electra, [0xad, 0x53, 0x2c, 0xeb]),
+    ] {
+        assert_eq!(fork_context.fork_digest(fork), digest, "{fork}");
+        assert_eq!(fork_context.fork_from_digest(digest), Some(fork));
+    }
+    // Fulu is not scheduled.
+    assert_eq!(
+        fork_context.fork_from_digest(fork_context.fork_digest(Fork::Fulu)),
+        None
+    );
+    assert_ne!(
+        ForkContext::new(ForkSchedule::default(), slots_per_epoch, [0; 32])
+            .with_fork_version(Fork::Deneb, [4, 0, 0, 1])
+            .fork_digest(Fork::Deneb),
+        ForkContext::new(ForkSchedule::default(), slots_per_epoch, [0; 32])
+            .fork_digest(Fork::Deneb)
+    );
+
+    let attestation = |slot| Message::Attestation {
+        attester: ValId(1),
+        subnet: Subnet(12),
+        slot: Slot::new(slot),
+    };
+    assert_eq!(
+        attestation(0).topic(&fork_context).to_string(),
+        "/eth2/b5303f2a/beacon_attestation_12/ssz_snappy"
+    );
+    // The digest follows the fork of the message's slot.
+    assert_eq!(
+        attestation(4 * slots_per_epoch)
+            .topic(&fork_context)
+            .to_string(),
+        "/eth2/6a95a1a9/beacon_attestation_12/ssz_snappy"
+    );
+    for (message, topic) in [
+        (
+            Message::BeaconBlock {
+                proposer: ValId(1),
+                slot: Slot::new(0),
+            },
+            "/eth2/b5303f2a/beacon_block/ssz_snappy",
+        ),
+        // Aggregates are published on a single topic whatever their subnet.
+        (
+            Message::AggregateAndProofAttestation {
+                aggregator: ValId(1),
+                subnet: Subnet(3),
+                slot: Slot::new(0),
+            },
+            "/eth2/b5303f2a/beacon_aggregate_and_proof/ssz_snappy",
+        ),
+        (
+            Message::SyncCommitteeMessage {
+                validator: ValId(1),
+                subnet: Subnet(3),
+                slot: Slot::new(slots_per_epoch),
+            },
+            "/eth2/afcaaba0/sync_committee_3/ssz_snappy",
+        ),
+        (
+            Message::SignedContributionAndProof {
+                validator: ValId(1),
+                subnet: Subnet(3),
+                slot: Slot::new(slots_per_epoch),
+            },
+            "/eth2/afcaaba0/sync_committee_contribution_and_proof/ssz_snappy",
+        ),
+        (
+            Message::BlobSidecar {
+                proposer: ValId(1),
+                index: 2,
+                subnet: Subnet(2),
+                slot: Slot::new(4 * slots_per_epoch),
+            },
+            "/eth2/6a95a1a9/blob_sidecar_2/ssz_snappy",
+        ),
+        (
+            Message::BlsToExecutionChange {
+                validator: ValId(1),
+                slot: Slot::new(5 * slots_per_epoch),
+            },
+            "/eth2/ad532ceb/bls_to_execution_change/ssz_snappy",
+        ),
+    ] {
+        assert_eq!(message.topic(&fork_context).to_string(), topic);
+    }
+
+    // Topics of every generated message parse back.
+    let total_validators = 4096;
+    let generator = Generator::builder()
+        .total_validators(total_validators)
+        .fork(Fork::Fulu)
+        .blob_count(BlobCount::Fixed(1))
+        .serve_light_clients(true)
+        .operation_rate(MsgType::VoluntaryExit, 8.0)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+    let fork_context = generator.fork_context(mainnet_genesis_validators_root);
+    let mut topic_types = HashSet::new();
+    for TimedMessage { message, .. } in generator.messages_for_slots(Slot::new(0)..Slot::new(64)) {
+        let topic = message.topic(&fork_context);
+        assert_eq!(topic.to_string().parse(), Ok(topic));
+        assert_eq!(
+            fork_context.fork_from_digest(topic.fork_digest),
+            Some(Fork::Fulu)
+        );
+        topic_types.insert(topic.msg_type);
+    }
+    assert!(topic_types.contains(&MsgType::DataColumnSidecar));
+    assert!(topic_types.contains(&MsgType::LightClientOptimisticUpdate));
+    assert!(topic_types.contains(&MsgType::VoluntaryExit));
+
+    for topic in [
+        "/eth2/b5303f2a/beacon_attestation/ssz_snappy",
+        "/eth2/b5303f2a/beacon_attestation_012/ssz_snappy",
+        // Subnets beyond those of the specs.
+        "/eth2/b5303f2a/beacon_attestation_64/ssz_snappy",
+        "/eth2/b5303f2a/beacon_attestation_999/ssz_snappy",
+        "/eth2/b5303f2a/sync_committee_4/ssz_snappy",
+        "/eth2/b5303f2a/blob_sidecar_9/ssz_snappy",
+        "/eth2/b5303f2a/data_column_sidecar_128/ssz_snappy",
+        "/eth2/b5303f2a/beacon_block_1/ssz_snappy",
+        "/eth2/b5303f2a/beacon_block/ssz",
+        "/eth2/b5303f/beacon_block/ssz_snappy",
+        "/eth2/+5303f2a/beacon_block/ssz_snappy",
+        "/eth2/b5303f2a/unknown/ssz_snappy",
+    ] {
+        assert!(topic.parse::<GossipTopic>().is_err(), "{topic}");
+    }
+    for topic in [
+        "/eth2/b5303f2a/beacon_attestation_63/ssz_snappy",
+        "/eth2/b5303f2a/sync_committee_3/ssz_snappy",
+        "/eth2/b5303f2a/blob_sidecar_8/ssz_snappy",
+        "/eth2/b5303f2a/data_column_sidecar_127/ssz_snappy",
+    ] {
+        assert!(topic.parse::<GossipTopic>().is_ok(), "{topic}");
+    }
+}
+
+#[test]
+fn test_gossip_topics_of_custom_subnets() {
+    use types::Epoch;
+
+    // More subnets than the specs define, and the blob subnets Electra adds.
+    let total_validators = 32768;
+    let slots_per_epoch = 32;
+    let generator = Generator::builder()
+        .total_validators(total_validators)
+        .attestation_subnets(128)
+        .sync_committee_subnets(8)
+        .fork_schedule(
+            ForkSchedule::from_genesis(Fork::Electra).with_fork_epoch(Fork::Fulu, Epoch::new(1)),
+        )
+        .blob_count(BlobCount::Fixed(spec::MAX_BLOBS_PER_BLOCK_ELECTRA))
+        .build((0..4096).map(ValId).collect())
+        .unwrap();
+    let fork_context = generator.fork_context([1; 32]);
+
+    let mut topics = HashSet::new();
+    for TimedMessage { message, .. } in
+        generator.messages_for_slots(Slot::new(0)..Slot::new(2 * slots_per_epoch))
+    {
+        let topic = message.topic(&fork_context);
+        assert_eq!(
+            GossipTopic::parse(&topic.to_string(), &fork_context),
+            Ok(topic)
+        );
+        topics.insert(topic);
+    }
+    let max_subnet = |msg_type| {
+        topics
+            .iter()
+            .filter(|topic| topic.msg_type == msg_type)
+            .filter_map(|topic| topic.subnet.map(|subnet| *subnet))
+            .max()
+    };
+    assert_eq!(max_subnet(MsgType::Attestation), Some(127));
+    assert_eq!(max_subnet(MsgType::SyncCommitteeMessage), Some(7));
+    assert_eq!(max_subnet(MsgType::BlobSidecar), Some(8));
+    assert_eq!(
+        max_subnet(MsgType::DataColumnSidecar),
+        Some(spec::DATA_COLUMN_SIDECAR_SUBNET_COUNT - 1)
+    );
+
+    // Subnets are bounded by the chain's counts and the fork of the digest.
+    let digest = |fork| {
+        fork_context
+            .fork_digest(fork)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>()
+    };
+    let parse = |fork, name: &str| {
+        GossipTopic::parse(
+            &format!("/eth2/{}/{name}/ssz_snappy", digest(fork)),
+            &fork_context,
+        )
+    };
+    assert!(parse(Fork::Electra, "beacon_attestation_128").is_err());
+    assert!(parse(Fork::Electra, "sync_committee_8").is_err());
+    assert!(parse(Fork::Electra, "blob_sidecar_9").is_err());
+    assert!(parse(Fork::Electra, "blob_sidecar_8").is_ok());
+    // Unknown to the chain.
+    assert!(GossipTopic::parse("/eth2/00000000/beacon_block/ssz_snappy", &fork_context).is_err());
+}
+
+#[test]
+fn test_message_ids() {
+    let topic: GossipTopic = "/eth2/6a95a1a9/beacon_attestation_12/ssz_snappy"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        Message::message_id(&[1, 2, 3], &topic),
+        [
+            0xdc, 0x3b, 0x60, 0xd8, 0x8b, 0x47, 0x63, 0x1e, 0x82, 0x82, 0x10, 0xde, 0xba, 0xdf,
+            0xc1, 0x62, 0x9d, 0xc1, 0xe3, 0xa4
+        ]
+    );
+
+    // The same payload gets a different id on another topic.
+    let other_topic = GossipTopic {
+        subnet: Some(Subnet(13)),
+        ..topic
+    };
+    assert_ne!(
+        Message::message_id(&[1, 2, 3], &topic),
+        Message::message_id(&[1, 2, 3], &other_topic)
+    );
+
+    // Generated messages get distinct ids, so none is deduplicated as a repeat of another.
+    let total_validators = 4096;
+    let generator = Generator::builder()
+        .total_validators(total_validators)
+        .blob_count(BlobCount::Fixed(2))
+        .seed(5)
+        .build((0..total_validators).map(ValId).collect())
+        .unwrap();
+    let fork_context = generator.fork_context([0; 32]);
+    let mut message_ids = HashSet::new();
+    for TimedMessage { message, .. } in generator.messages_for_slots(Slot::new(0)..Slot::new(32)) {
+        let payload = generator.payload(&message);
+        let message_id = Message::message_id(&payload, &message.topic(&fork_context));
+        assert!(
+            message_ids.insert(message_id),
+            "{} in {:?}",
+            message.msg_type(),
+            message.slot()
+        );
+    }
+}
+
+#[test]
+fn test_realistic_payloads() {
+    let total_validators = 4096;
+    let build = |payload_mode| {
+        Generator::builder()
+            .total_validators(total_validators)
+            .blob_count(BlobCount::Fixed(1))
+            .serve_light_clients(true)
+            .payload_mode(payload_mode)
+            .seed(2)
+            .build((0..total_validators).map(ValId).collect())
+            .unwrap()
+    };
+    let zeroed = build(PayloadMode::Zeroed);
+    let realistic = build(PayloadMode::Realistic);
+
+    let mut decoder = snap::raw::Decoder::new();
+    let mut compressed_sizes = HashMap::<MsgType, Vec<usize>>::new();
+    for TimedMessage { message, .. } in realistic.messages_for_slots(Slot::new(0)..Slot::new(8)) {
+        let payload = realistic.payload(&message);
+        let encoded = realistic.encoded_payload(&message);
+        assert_eq!(decoder.decompress_vec(&encoded).unwrap(), payload);
+        assert_eq!(encoded, sizes::encode_ssz_snappy(&payload));
+        // Zeroed payloads compress to a small fraction of the realistic ones.
+        assert!(zeroed.encoded_payload(&message).len() * 4 < encoded.len());
+        compressed_sizes
+            .entry(message.msg_type())
+            .or_default()
+            .push(encoded.len());
+    }
+
+    // Compressed sizes follow the sizes measured on the wire.
+    for (msg_type, wire_sizes) in [
+        (MsgType::BeaconBlock, 30_000..70_000),
+        (MsgType::Attestation, 200..310),
+        (MsgType::AggregateAndProofAttestation, 500..550),
+        (MsgType::SyncCommitteeMessage, 190..210),
+        (MsgType::SignedContributionAndProof, 410..430),
+    ] {
+        for size in &compressed_sizes[&msg_type] {
+            let size = *size as f64;
+            assert!(
+                size >= wire_sizes.start as f64 * 0.97 && size <= wire_sizes.end as f64 * 1.03,
+                "{msg_type} compressed to {size} bytes"
+            );
+        }
+    }
+    // Messages with a known SSZ size keep it, and barely compress.
+    let blob_sidecar = Message::BlobSidecar {
+        proposer: ValId(0),
+        index: 0,
+        subnet: Subnet(0),
+        slot: Slot::new(0),
+    };
+    assert_eq!(realistic.payload(&blob_sidecar).len(), 131_928);
+    for size in &compressed_sizes[&MsgType::BlobSidecar] {
+        assert!((110_000..131_928).contains(size), "{size}");
+    }
+}
@@ -0,0 +1,164 @@
+//! Gossipsub topics on which messages are published.
+
+use std::{fmt, str::FromStr};
+
+use strum::IntoEnumIterator;
+
+use crate::{fork::ForkContext, slot_generator::Subnet, spec, Message, MsgType};
+
+/// Encoding of the messages of every topic.
+pub const ENCODING: &str = "ssz_snappy";
+
+/// Gossip topic, formatted as `/eth2/{fork_digest}/{name}/ssz_snappy`. Topics of message types
+/// gossiped over subnets end their name with the subnet, as in `beacon_attestation_{subnet}`, and
+/// only parse for subnets that exist: those of the specs, or those of the chain when parsed with
+/// [`GossipTopic::parse`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GossipTopic {
+    pub fork_digest: [u8; 4],
+    pub msg_type: MsgType,
+    /// Subnet of the topic, for message types gossiped over subnets.
+    pub subnet: Option<Subnet>,
+}
+
+impl MsgType {
+    /// Name of the topic on which messages of this type are published, without the subnet. Same
+    /// as the type's name in snake case, except where the specs name the topic otherwise.
+    pub fn topic_name(&self) -> String {
+        match self {
+            MsgType::AggregateAndProofAttestation => "beacon_aggregate_and_proof".into(),
+            MsgType::Attestation => "beacon_attestation".into(),
+            MsgType::SignedContributionAndProof => "sync_committee_contribution_and_proof".into(),
+            MsgType::SyncCommitteeMessage => "sync_committee".into(),
+            _ => self.to_string().replace('-', "_"),
+        }
+    }
+
+    /// Whether messages of this type are published over subnets, each with its own topic.
+    pub fn has_subnets(&self) -> bool {
+        self.subnet_count().is_some()
+    }
+
+    /// Number of subnets messages of this type are published over, as in the specs. Blob
+    /// sidecars take the largest count of any fork, for topics parsed without knowing the fork of
+    /// their digest.
+    pub fn subnet_count(&self) -> Option<u64> {
+        match self {
+            MsgType::BlobSidecar => Some(spec::BLOB_SIDECAR_SUBNET_COUNT_ELECTRA),
+            MsgType::DataColumnSidecar => Some(spec::DATA_COLUMN_SIDECAR_SUBNET_COUNT),
+            MsgType::Attestation => Some(spec::ATTESTATION_SUBNET_COUNT),
+            MsgType::SyncCommitteeMessage => Some(spec::SYNC_COMMITTEE_SUBNET_COUNT),
+            _ => None,
+        }
+    }
+}
+
+impl Message {
+    /// Subnet the message is published on. Aggregates carry the subnet of their committee, but
+    /// are published on a single topic.
+    pub fn subnet(&self) -> Option<Subnet> {
+        match self {
+            Message::BlobSidecar { subnet, .. }
+            | Message::DataColumnSidecar { subnet, .. }
+            | Message::AggregateAndProofAttestation { subnet, .. }
+            | Message::Attestation { subnet, .. }
+            | Message::SignedContributionAndProof { subnet, .. }
+            | Message::SyncCommitteeMessage { subnet, .. } => Some(*subnet),
+            _ => None,
+        }
+    }
+
+    /// Topic on which the message is published, with the digest of the fork of its slot.
+    pub fn topic(&self, fork_context: &ForkContext) -> GossipTopic {
+        let msg_type = self.msg_type();
+        GossipTopic {
+            fork_digest: fork_context.fork_digest(fork_context.fork_at(self.slot())),
+            msg_type,
+            subnet: self.subnet().filter(|_| msg_type.has_subnets()),
+        }
+    }
+}
+
+impl fmt::Display for GossipTopic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "/eth2/")?;
+        for byte in self.fork_digest {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, "/{}", self.msg_type.topic_name())?;
+        if let Some(subnet) = self.subnet {
+            write!(f, "_{}", *subnet)?;
+        }
+        write!(f, "/{ENCODING}")
+    }
+}
+
+impl GossipTopic {
+    /// Parses a topic of the chain. Subnets are bounded by the counts of the context and the fork
+    /// of the topic's digest, which must be scheduled.
+    pub fn parse(topic: &str, fork_context: &ForkContext) -> Result<Self, &'static str> {
+        let (fork_digest, name) = split_topic(topic)?;
+        let fork = fork_context
+            .fork_from_digest(fork_digest)
+            .ok_or("unknown fork digest")?;
+        parse_name(fork_digest, name, |msg_type| {
+            fork_context.subnet_count(msg_type, fork)
+        })
+    }
+}
+
+impl FromStr for GossipTopic {
+    type Err = &'static str;
+
+    /// Parses a topic of any chain. Subnets are bounded by the counts of the specs.
+    fn from_str(topic: &str) -> Result<Self, Self::Err> {
+        let (fork_digest, name) = split_topic(topic)?;
+        parse_name(fork_digest, name, |msg_type| msg_type.subnet_count())
+    }
+}
+
+/// Fork digest and name of a topic.
+fn split_topic(topic: &str) -> Result<([u8; 4], &str), &'static str> {
+    let parts: Vec<_> = topic.split('/').collect();
+    let ["", "eth2", fork_digest, name, ENCODING] = parts[..] else {
+        return Err("topics must look like /eth2/{fork_digest}/{name}/ssz_snappy");
+    };
+    if fork_digest.len() != 8 || !fork_digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("fork digests must be 4 hex encoded bytes");
+    }
+    let fork_digest = u32::from_str_radix(fork_digest, 16)
+        .expect("fork digest is 8 hex digits")
+        .to_be_bytes();
+    Ok((fork_digest, name))
+}
+
+/// Topic with the given name, whose subnet must be below the count of its message type.
+fn parse_name(
+    fork_digest: [u8; 4],
+    name: &str,
+    subnet_count: impl Fn(MsgType) -> Option<u64>,
+) -> Result<GossipTopic, &'static str> {
+    MsgType::iter()
+        .find_map(|msg_type| {
+            let topic_name = msg_type.topic_name();
+            let Some(subnet_count) = subnet_count(msg_type) else {
+                return (name == topic_name).then_some((msg_type, None));
+            };
+            let subnet = name.strip_prefix(&topic_name)?.strip_prefix('_')?;
+            // Leading signs and zeros would give the same subnet different topics.
+            if subnet.starts_with(['+', '0']) && subnet != "0" {
+                return None;
+            }
+            let subnet = subnet
+                .parse()
+                .ok()
+                .filter(|subnet| *subnet < subnet_count)?;
+            Some((msg_type, Some(Subnet(subnet))))
+        })
+        .map(|(msg_type, subnet)| GossipTopic {
+            fork_digest,
+            msg_type,
+            subnet,
+        })
+        .ok_or("unknown topic name")
+}